@@ -0,0 +1,652 @@
+//! Validation of a config file (`anymon check`), pointing every problem at
+//! its line and column.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use toml::de::{DeTable, DeValue};
+
+use crate::{parse_glob, Config, GlobalConfig, RunConfig, Shell, TaskConfig};
+
+const HOOK_KEYS: [&str; 4] = ["before", "after", "on_success", "on_failure"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Probably a mistake, but the config still loads.
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Byte range in the file the problem is about.
+    pub span: Option<Range<usize>>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    fn error(message: impl Into<String>, span: Option<Range<usize>>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    fn warning(message: impl Into<String>, span: Option<Range<usize>>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    fn help(self, help: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            help: Some(help.into()),
+            ..self
+        }
+    }
+}
+
+/// A config file and everything wrong with it.
+#[derive(Debug)]
+pub struct Report {
+    pub path: PathBuf,
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The parsed config, unless the file isn't valid TOML or doesn't fit
+    /// the config's types.
+    pub config: Option<Config>,
+}
+
+impl Report {
+    /// Read and check the config file at `path`.
+    pub fn load(path: &Path) -> std::io::Result<Report> {
+        let source = std::fs::read_to_string(path)?;
+        let base_dir = std::fs::canonicalize(path)?.parent().map(Path::to_path_buf);
+        Ok(Report::new(path.to_path_buf(), source, base_dir))
+    }
+
+    /// Check `source`; relative paths in it are resolved against `base_dir`.
+    pub fn new(path: PathBuf, source: String, base_dir: Option<PathBuf>) -> Report {
+        let mut diagnostics = Vec::new();
+        let config = check(&source, base_dir, &mut diagnostics);
+        diagnostics.sort_by_key(|d| d.span.as_ref().map_or(usize::MAX, |span| span.start));
+        Report {
+            path,
+            source,
+            diagnostics,
+            config,
+        }
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+
+    /// The diagnostics of at least `min` severity, each with the line it
+    /// points at:
+    ///
+    /// ```text
+    /// error: unknown key `wacth` in [[task]]
+    ///  --> anymon.toml:5:1
+    ///   |
+    /// 5 | wacth = ["src/**"]
+    ///   | ^^^^^
+    ///   = help: did you mean `watch`?
+    /// ```
+    pub fn render(&self, min: Severity) -> String {
+        let mut out = String::new();
+        for d in self.diagnostics.iter().filter(|d| d.severity >= min) {
+            let label = match d.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            let _ = writeln!(out, "{}: {}", label, d.message);
+            let Some(span) = &d.span else {
+                let _ = writeln!(out, " --> {}", self.path.display());
+                if let Some(help) = &d.help {
+                    let _ = writeln!(out, "  = help: {}", help);
+                }
+                continue;
+            };
+            let (line, col) = position(&self.source, span.start);
+            let text = self.source.lines().nth(line - 1).unwrap_or_default();
+            let width =
+                (self.source[span.clone()].lines().next()).map_or(1, |s| s.chars().count().max(1));
+            let pad = " ".repeat(line.to_string().len());
+            let _ = writeln!(out, "{}--> {}:{}:{}", pad, self.path.display(), line, col);
+            let _ = writeln!(out, "{} |", pad);
+            let _ = writeln!(out, "{} | {}", line, text);
+            let _ = writeln!(
+                out,
+                "{} | {}{}",
+                pad,
+                " ".repeat(col - 1),
+                "^".repeat(width)
+            );
+            if let Some(help) = &d.help {
+                let _ = writeln!(out, "{} = help: {}", pad, help);
+            }
+        }
+        out
+    }
+}
+
+/// 1-based line and column of byte `offset`.
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, col)
+}
+
+fn check(source: &str, base_dir: Option<PathBuf>, out: &mut Vec<Diagnostic>) -> Option<Config> {
+    let (doc, errors) = DeTable::parse_recoverable(source);
+    // Errors after the first are mostly the parser losing track.
+    if let Some(e) = errors.first() {
+        out.push(Diagnostic::error(e.message(), e.span()));
+        return None;
+    }
+    let doc = doc.get_ref();
+    check_keys(doc, out);
+
+    let mut config: Config = match toml::from_str(source) {
+        Ok(config) => config,
+        Err(e) => {
+            out.push(Diagnostic::error(e.message(), e.span()));
+            return None;
+        }
+    };
+    config.base_dir = base_dir;
+
+    if let Some(global) = doc.get("global").and_then(|v| as_table(v.get_ref())) {
+        for (j, pat) in config
+            .global
+            .iter()
+            .flat_map(|g| g.ignore.iter().flatten())
+            .enumerate()
+        {
+            if let Err(e) = parse_glob(pat) {
+                out.push(Diagnostic::error(
+                    format!("global: invalid ignore pattern {}", e),
+                    element_span(Some(global), "ignore", j),
+                ));
+            }
+        }
+        if let Some(file) = config.global.as_ref().and_then(|g| g.env_file.as_ref()) {
+            let span = value_span(Some(global), "env_file");
+            check_env_file(&config, "global", file, span, out);
+        }
+    }
+
+    let tasks = config.task.as_deref().unwrap_or_default();
+    let tables: Vec<Option<&DeTable>> = (0..tasks.len()).map(|i| task_table(doc, i)).collect();
+    let mut unknown_deps = false;
+    for (i, t) in tasks.iter().enumerate() {
+        let table = tables[i];
+        if let Some(first) = tasks[..i].iter().position(|other| other.name == t.name) {
+            let mut d = Diagnostic::error(
+                format!("duplicate task name '{}'", t.name),
+                value_span(table, "name"),
+            );
+            if let Some(span) = value_span(tables[first], "name") {
+                d = d.help(format!(
+                    "first used on line {}",
+                    position(source, span.start).0
+                ));
+            }
+            out.push(d);
+        }
+
+        if t.watch.is_empty() && t.depends_on.as_ref().is_none_or(Vec::is_empty) {
+            out.push(
+                Diagnostic::warning(
+                    format!(
+                        "task '{}' has an empty watch list, so file changes never trigger it",
+                        t.name
+                    ),
+                    value_span(table, "watch"),
+                )
+                .help("use [\"**\"] to watch every file"),
+            );
+        }
+        for (key, patterns) in [("watch", Some(&t.watch)), ("ignore", t.ignore.as_ref())] {
+            for (j, pat) in patterns.into_iter().flatten().enumerate() {
+                let pat = pat.strip_prefix('!').unwrap_or(pat);
+                if let Err(e) = parse_glob(pat) {
+                    out.push(Diagnostic::error(
+                        format!("task '{}': invalid {} pattern {}", t.name, key, e),
+                        element_span(table, key, j),
+                    ));
+                }
+            }
+        }
+        for (j, dep) in t.depends_on.iter().flatten().enumerate() {
+            if !tasks.iter().any(|other| &other.name == dep) {
+                unknown_deps = true;
+                out.push(Diagnostic::error(
+                    format!("task '{}': depends_on unknown task '{}'", t.name, dep),
+                    element_span(table, "depends_on", j),
+                ));
+            }
+        }
+
+        if let Some(dir) = &t.cwd {
+            let dir = config.resolve_path(dir);
+            if !dir.is_dir() {
+                out.push(Diagnostic::error(
+                    format!(
+                        "task '{}': cwd {} is not a directory",
+                        t.name,
+                        dir.display()
+                    ),
+                    value_span(table, "cwd"),
+                ));
+            }
+        }
+        if let Some(file) = &t.env_file {
+            let who = format!("task '{}'", t.name);
+            check_env_file(&config, &who, file, value_span(table, "env_file"), out);
+        }
+
+        check_command(&config, t, &t.run, value_span(table, "run"), out);
+        let hooks = [&t.before, &t.after, &t.on_success, &t.on_failure];
+        for (key, hooks) in HOOK_KEYS.into_iter().zip(hooks) {
+            for (j, hook) in hooks.iter().flatten().enumerate() {
+                check_command(&config, t, hook, element_span(table, key, j), out);
+            }
+        }
+    }
+    // Cycles are only meaningful once every dependency exists.
+    if !unknown_deps {
+        if let Err(e) = config.check_dependencies() {
+            out.push(Diagnostic::error(e.to_string(), None));
+        }
+    }
+
+    Some(config)
+}
+
+fn check_env_file(
+    config: &Config,
+    who: &str,
+    file: &Path,
+    span: Option<Range<usize>>,
+    out: &mut Vec<Diagnostic>,
+) {
+    let file = config.resolve_path(file);
+    if !file.is_file() {
+        out.push(Diagnostic::error(
+            format!("{}: env_file {} not found", who, file.display()),
+            span,
+        ));
+    }
+}
+
+/// Report unknown keys in the document, suggesting the closest known one.
+fn check_keys(doc: &DeTable, out: &mut Vec<Diagnostic>) {
+    fn unknown(table: &DeTable, known: &[&str], section: &str, out: &mut Vec<Diagnostic>) {
+        for (key, _) in table.iter() {
+            let name: &str = key.get_ref();
+            if known.contains(&name) {
+                continue;
+            }
+            let mut d = Diagnostic::error(
+                format!("unknown key `{}` in {}", name, section),
+                Some(key.span()),
+            );
+            if let Some(close) = suggest(name, known) {
+                d = d.help(format!("did you mean `{}`?", close));
+            }
+            out.push(d);
+        }
+    }
+
+    unknown(doc, struct_keys::<Config>(), "the config", out);
+    if let Some(global) = doc.get("global").and_then(|v| as_table(v.get_ref())) {
+        unknown(global, struct_keys::<GlobalConfig>(), "[global]", out);
+    }
+    let task_keys = struct_keys::<TaskConfig>();
+    for i in 0.. {
+        match task_table(doc, i) {
+            Some(task) => unknown(task, task_keys, "[[task]]", out),
+            None => break,
+        }
+    }
+}
+
+/// The keys `T`'s derived `Deserialize` accepts, as serde hands them to the
+/// deserializer, so that the known keys can't drift from the structs.
+fn struct_keys<'de, T: serde::Deserialize<'de>>() -> &'static [&'static str] {
+    use serde::de::{Error, Visitor};
+
+    struct Keys<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for Keys<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(Error::custom("only collecting keys"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut keys: &'static [&'static str] = &[];
+    let _ = T::deserialize(Keys(&mut keys));
+    keys
+}
+
+/// Check that a command can be started: it parses, and its program (or the
+/// shell it runs in) can be found.
+fn check_command(
+    config: &Config,
+    t: &TaskConfig,
+    cmd: &RunConfig,
+    span: Option<Range<usize>>,
+    out: &mut Vec<Diagnostic>,
+) {
+    let global = config.global.as_ref();
+    let shell = t.shell.or(global.and_then(|g| g.shell)).unwrap_or_default();
+    let program = match (cmd, shell) {
+        (RunConfig::Argv(argv), _) => argv.first().cloned(),
+        (RunConfig::Line(_), Shell::Bash) => Some("bash".to_string()),
+        (RunConfig::Line(_), Shell::Sh) => Some("sh".to_string()),
+        (RunConfig::Line(_), Shell::Pwsh) => Some("pwsh".to_string()),
+        (RunConfig::Line(line), Shell::Auto | Shell::None) => {
+            // Variables set in the config take part in the expansion too.
+            let mut vars = BTreeMap::new();
+            for env in [global.and_then(|g| g.env.as_ref()), t.env.as_ref()] {
+                vars.extend(env.into_iter().flatten());
+            }
+            let lookup = |name: &str| {
+                vars.get(&name.to_string())
+                    .map(|v| v.to_string())
+                    .or_else(|| std::env::var(name).ok())
+            };
+            match anymon_shell::parse_command_line_with(line, lookup) {
+                Ok(anymon_shell::CommandLine::Argv(words)) => words.into_iter().next(),
+                Ok(anymon_shell::CommandLine::Shell) if shell == Shell::None => {
+                    out.push(Diagnostic::error(
+                        format!(
+                            "task '{}': command uses shell syntax but shell = \"none\"",
+                            t.name
+                        ),
+                        span,
+                    ));
+                    return;
+                }
+                // Runs through the platform shell.
+                Ok(anymon_shell::CommandLine::Shell) => return,
+                Err(e) => {
+                    out.push(Diagnostic::error(
+                        format!("task '{}': cannot parse command: {}", t.name, e),
+                        span,
+                    ));
+                    return;
+                }
+            }
+        }
+    };
+    let Some(program) = program else {
+        out.push(Diagnostic::error(
+            format!("task '{}': empty command", t.name),
+            span,
+        ));
+        return;
+    };
+    // Placeholders are only filled in when the command runs.
+    if program.contains('{') {
+        return;
+    }
+    let cwd = t.cwd.as_ref().map(|dir| config.resolve_path(dir));
+    if anymon_shell::find_executable(&program, cwd.as_deref()).is_none() {
+        out.push(
+            Diagnostic::warning(
+                format!("task '{}': program '{}' not found", t.name, program),
+                span,
+            )
+            .help("fine if it is created before the task runs, e.g. by a dependency"),
+        );
+    }
+}
+
+fn as_table<'a, 'i>(value: &'a DeValue<'i>) -> Option<&'a DeTable<'i>> {
+    match value {
+        DeValue::Table(table) => Some(table),
+        _ => None,
+    }
+}
+
+/// The `i`th `[[task]]` table.
+fn task_table<'a, 'i>(doc: &'a DeTable<'i>, i: usize) -> Option<&'a DeTable<'i>> {
+    match doc.get("task")?.get_ref() {
+        DeValue::Array(tasks) => as_table(tasks.get(i)?.get_ref()),
+        _ => None,
+    }
+}
+
+fn value_span(table: Option<&DeTable>, key: &str) -> Option<Range<usize>> {
+    Some(table?.get(key)?.span())
+}
+
+fn element_span(table: Option<&DeTable>, key: &str, i: usize) -> Option<Range<usize>> {
+    match table?.get(key)?.get_ref() {
+        DeValue::Array(items) => Some(items.get(i)?.span()),
+        _ => None,
+    }
+}
+
+/// The known key closest to `name`, if it is close enough to be a typo.
+fn suggest<'a>(name: &str, known: &[&'a str]) -> Option<&'a str> {
+    let limit = name.chars().count() / 3 + 1;
+    known
+        .iter()
+        .map(|k| (edit_distance(name, k), *k))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_str(source: &str) -> Report {
+        Report::new("anymon.toml".into(), source.to_string(), None)
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_duplicates() {
+        let report = check_str(
+            r#"[global]
+debounce = 50
+
+[[task]]
+name = "web"
+wacth = ["src/**"]
+watch = ["src/**"]
+run = ["true"]
+
+[[task]]
+name = "web"
+watch = []
+run = ["true"]
+"#,
+        );
+        assert_eq!(report.errors(), 2);
+        assert_eq!(report.warnings(), 1);
+        assert_eq!(
+            report.render(Severity::Error),
+            "error: unknown key `wacth` in [[task]]
+ --> anymon.toml:6:1
+  |
+6 | wacth = [\"src/**\"]
+  | ^^^^^
+  = help: did you mean `watch`?
+error: duplicate task name 'web'
+  --> anymon.toml:11:8
+   |
+11 | name = \"web\"
+   |        ^^^^^
+   = help: first used on line 5
+"
+        );
+        assert!(report.config.is_some());
+    }
+
+    #[test]
+    fn known_keys_come_from_the_structs() {
+        assert_eq!(struct_keys::<Config>(), ["global", "task"]);
+        let global = struct_keys::<GlobalConfig>();
+        assert!(global.contains(&"use_gitignore") && !global.contains(&"run"));
+        let task = struct_keys::<TaskConfig>();
+        assert!(["name", "run", "stop_signal", "log_keep"]
+            .iter()
+            .all(|key| task.contains(key)));
+    }
+
+    #[test]
+    fn reports_globs_programs_and_types() {
+        let report = check_str(
+            r#"[[task]]
+name = "web"
+watch = ["src/**", "src/[ab"]
+ignore = ["a/{b,c"]
+run = "definitely-not-a-program-anymon --flag"
+before = [["true"], []]
+"#,
+        );
+        let messages: Vec<(Severity, &str)> = report
+            .diagnostics
+            .iter()
+            .map(|d| (d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (
+                    Severity::Error,
+                    "task 'web': invalid watch pattern 'src/[ab' (column 5): \
+                     unclosed character class; missing ']'"
+                ),
+                (
+                    Severity::Error,
+                    "task 'web': invalid ignore pattern 'a/{b,c' (column 3): \
+                     unclosed alternate group; missing '}' (maybe escape '{' with '[{]'?)"
+                ),
+                (
+                    Severity::Warning,
+                    "task 'web': program 'definitely-not-a-program-anymon' not found"
+                ),
+                (Severity::Error, "task 'web': empty command"),
+            ]
+        );
+        assert_eq!(
+            position(
+                &report.source,
+                report.diagnostics[0].span.clone().unwrap().start
+            ),
+            (3, 20)
+        );
+
+        // `watch` must be an array.
+        let report = check_str("[[task]]\nname = \"web\"\nwatch = \"src/**\"\nrun = \"true\"\n");
+        assert_eq!(report.errors(), 1);
+        assert!(report.config.is_none());
+
+        // An unknown stop_signal is reported at its value.
+        let report = check_str(
+            "[[task]]\nname = \"web\"\nwatch = []\nrun = \"true\"\nstop_signal = \"SIGFOO\"\n",
+        );
+        assert_eq!(report.errors(), 1);
+        let error = &report.diagnostics[0];
+        assert!(error.message.starts_with("unknown stop_signal 'SIGFOO'"));
+        assert_eq!(
+            position(&report.source, error.span.clone().unwrap().start).0,
+            5
+        );
+    }
+
+    #[test]
+    fn reports_missing_paths() {
+        let dir = std::env::temp_dir().join(format!("anymon-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = r#"[global]
+env_file = "missing.env"
+
+[[task]]
+name = "web"
+watch = ["**"]
+run = ["true"]
+cwd = "nope"
+"#;
+        let report = Report::new("anymon.toml".into(), source.to_string(), Some(dir.clone()));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<(usize, &str)> = report
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let span = d.span.clone().unwrap();
+                (position(source, span.start).0, d.message.as_str())
+            })
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0, 2);
+        assert!(lines[0].1.starts_with("global: env_file ") && lines[0].1.ends_with(" not found"));
+        assert_eq!(lines[1].0, 8);
+        assert!(lines[1].1.ends_with("nope is not a directory"));
+    }
+}
@@ -1,30 +1,356 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub mod check;
+
+/// Config file names [`Config::discover`] looks for, in order.
+pub const CONFIG_FILES: [&str; 3] = ["Anymon.toml", "anymon.toml", ".anymon.toml"];
 
 #[derive(Debug, Deserialize)]
 pub struct GlobalConfig {
     pub debounce: Option<u64>,
     pub ignore: Option<Vec<String>>,
+    /// Default interpreter for tasks that don't set their own `shell`.
+    pub shell: Option<Shell>,
+    /// Environment variables for every task.
+    pub env: Option<BTreeMap<String, String>>,
+    /// `.env` file loaded for every task, relative to the config file.
+    pub env_file: Option<PathBuf>,
+    /// Default `output` for tasks that don't set their own.
+    pub output: Option<OutputMode>,
+    /// Default `log_file` for tasks that don't set their own.
+    pub log_file: Option<String>,
+    /// Default `log_max_size` for tasks that don't set their own.
+    pub log_max_size: Option<u64>,
+    /// Default `log_keep` for tasks that don't set their own.
+    pub log_keep: Option<usize>,
+    /// Skip paths ignored by `.gitignore`, `.ignore` and `.anymonignore`
+    /// files. Defaults to true.
+    pub use_gitignore: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TaskConfig {
     pub name: String,
+    /// Globs of files that trigger the task; entries starting with `!`
+    /// exclude files instead.
     pub watch: Vec<String>,
-    pub run: String,
+    /// Globs of files that never trigger this task.
+    pub ignore: Option<Vec<String>>,
+    pub run: RunConfig,
     pub restart: Option<bool>,
+    /// Run the command once per changed file (with `{file}` etc. set to that
+    /// file) instead of once per batch.
+    pub per_file: Option<bool>,
+    /// What a `restart = false` task does with changes that arrive while it
+    /// is still running. Defaults to `queue`.
+    pub on_busy: Option<OnBusy>,
+    /// Tasks that must succeed before this one starts; each later success
+    /// of one of them restarts this task.
+    pub depends_on: Option<Vec<String>>,
+    /// Commands run in order before each start of `run`.
+    pub before: Option<Vec<RunConfig>>,
+    /// Commands run after `run` exits on its own.
+    pub after: Option<Vec<RunConfig>>,
+    /// Commands run after `run` exits successfully.
+    pub on_success: Option<Vec<RunConfig>>,
+    /// Commands run after `run` fails.
+    pub on_failure: Option<Vec<RunConfig>>,
+    /// Start the process again when it exits on its own. Defaults to `never`.
+    pub restart_on_exit: Option<RestartOnExit>,
+    /// Delay (ms) before the first automatic restart; doubled after every
+    /// further exit in a row. Defaults to 500.
+    pub restart_delay: Option<u64>,
+    /// Automatic restarts in a row before giving up. Defaults to 5.
+    pub max_retries: Option<u32>,
+    /// Signal sent to stop the process before escalating to SIGKILL
+    /// (e.g. "SIGTERM", "SIGINT"). Defaults to SIGTERM.
+    pub stop_signal: Option<StopSignal>,
+    /// Interpreter used for `run` strings; overrides `global.shell`.
+    pub shell: Option<Shell>,
+    /// Working directory for the task, relative to the config file.
+    pub cwd: Option<PathBuf>,
+    /// Environment variables for the task; override `global.env`.
+    pub env: Option<BTreeMap<String, String>>,
+    /// `.env` file for the task, relative to the config file.
+    pub env_file: Option<PathBuf>,
+    /// How the task's output reaches the terminal; overrides
+    /// `global.output`.
+    pub output: Option<OutputMode>,
+    /// File the task's output is also written to, relative to the config
+    /// file; `{name}` is replaced by the task name.
+    pub log_file: Option<String>,
+    /// Size in bytes at which the log file is rotated. Defaults to 10 MiB.
+    pub log_max_size: Option<u64>,
+    /// Rotated log files kept next to the current one. Defaults to 5.
+    pub log_keep: Option<usize>,
+}
+
+/// Signal sent to a task's process when anymon wants it to stop. If the
+/// process is still alive after `kill_timeout` it is killed with SIGKILL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopSignal {
+    #[default]
+    Term,
+    Int,
+    Hup,
+    Quit,
+    Usr1,
+    Usr2,
+    Kill,
+}
+
+impl StopSignal {
+    pub const ALL: [StopSignal; 7] = [
+        StopSignal::Term,
+        StopSignal::Int,
+        StopSignal::Hup,
+        StopSignal::Quit,
+        StopSignal::Usr1,
+        StopSignal::Usr2,
+        StopSignal::Kill,
+    ];
+
+    /// Parse a signal name such as "SIGTERM", "TERM" or "int" (case-insensitive).
+    pub fn parse(name: &str) -> Option<Self> {
+        let upper = name.trim().to_ascii_uppercase();
+        let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+        StopSignal::ALL
+            .into_iter()
+            .find(|signal| &signal.name()[3..] == bare)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StopSignal::Term => "SIGTERM",
+            StopSignal::Int => "SIGINT",
+            StopSignal::Hup => "SIGHUP",
+            StopSignal::Quit => "SIGQUIT",
+            StopSignal::Usr1 => "SIGUSR1",
+            StopSignal::Usr2 => "SIGUSR2",
+            StopSignal::Kill => "SIGKILL",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StopSignal {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        StopSignal::parse(&name).ok_or_else(|| {
+            let names: Vec<&str> = StopSignal::ALL.iter().map(|s| s.name()).collect();
+            serde::de::Error::custom(format!(
+                "unknown stop_signal '{}', expected one of {}",
+                name,
+                names.join(", ")
+            ))
+        })
+    }
+}
+
+/// The `run` value of a task: either a command line that is split with
+/// shell-style quoting, or an argv array that is spawned exactly as given.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum RunConfig {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+/// Which interpreter runs a task's command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// Spawn directly, using the platform shell only for shell syntax or
+    /// programs that cannot be found.
+    #[default]
+    Auto,
+    Bash,
+    Sh,
+    Pwsh,
+    /// Never use a shell.
+    None,
+}
+
+/// How a run-to-completion task handles changes while it is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnBusy {
+    /// Run once more after the current run finishes; further changes are
+    /// folded into that one rerun.
+    #[default]
+    Queue,
+    /// Stop the current run and start over.
+    Restart,
+    /// Drop the changes.
+    Ignore,
+}
+
+/// When a process that exited on its own is started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartOnExit {
+    #[default]
+    Never,
+    /// Only after a non-zero exit status or a signal.
+    OnFailure,
+    Always,
+}
+
+/// How a task's stdout and stderr reach the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Read by anymon and printed line by line behind the task's name.
+    #[default]
+    Prefix,
+    /// Passed straight through, for programs that need a real terminal.
+    Inherit,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub global: Option<GlobalConfig>,
     pub task: Option<Vec<TaskConfig>>,
+    /// Directory containing the config file, set by [`Config::from_toml`].
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
 }
 
 impl Config {
-    pub fn from_toml(path: &str) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        Ok(config)
+    /// Load and validate the config at `path`. Fails with every error
+    /// [`check::Report`] finds, rendered with file positions.
+    pub fn from_toml(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let report = check::Report::load(path.as_ref())?;
+        match report.config {
+            Some(config) if report.errors() == 0 => Ok(config),
+            _ => anyhow::bail!("{}", report.render(check::Severity::Error).trim_end()),
+        }
+    }
+
+    /// Find the config for `dir`: the first of [`CONFIG_FILES`] in `dir` or
+    /// one of its parents, stopping at the root of the git repository.
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        for dir in dir.ancestors() {
+            if let Some(file) = CONFIG_FILES
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|file| file.is_file())
+            {
+                return Some(file);
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        None
+    }
+
+    /// Make sure every `depends_on` entry names a task and that the
+    /// dependencies don't form a cycle.
+    pub fn check_dependencies(&self) -> anyhow::Result<()> {
+        let tasks = self.task.as_deref().unwrap_or_default();
+        for t in tasks {
+            for dep in t.depends_on.iter().flatten() {
+                if !tasks.iter().any(|other| &other.name == dep) {
+                    anyhow::bail!("task '{}': depends_on unknown task '{}'", t.name, dep);
+                }
+            }
+        }
+
+        // Depth-first search; `path` is the chain of tasks being visited.
+        fn visit<'a>(
+            name: &'a str,
+            tasks: &'a [TaskConfig],
+            path: &mut Vec<&'a str>,
+            done: &mut Vec<&'a str>,
+        ) -> anyhow::Result<()> {
+            if done.contains(&name) {
+                return Ok(());
+            }
+            if let Some(start) = path.iter().position(|n| *n == name) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(name);
+                anyhow::bail!("dependency cycle: {}", cycle.join(" -> "));
+            }
+            path.push(name);
+            for t in tasks.iter().filter(|t| t.name == name) {
+                for dep in t.depends_on.iter().flatten() {
+                    visit(dep, tasks, path, done)?;
+                }
+            }
+            path.pop();
+            done.push(name);
+            Ok(())
+        }
+        let mut done = Vec::new();
+        for t in tasks {
+            visit(&t.name, tasks, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve a path from the config relative to the config file's
+    /// directory (or the current directory if the config wasn't loaded from
+    /// a file).
+    pub fn resolve_path(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        match &self.base_dir {
+            Some(dir) => dir.join(path),
+            None => std::env::current_dir()
+                .map(|d| d.join(path))
+                .unwrap_or_else(|_| path.to_path_buf()),
+        }
+    }
+}
+
+/// Parse `pattern` as a glob. Errors quote the pattern and, where it can be
+/// told, the column of the offending character.
+pub fn parse_glob(pattern: &str) -> Result<globset::Glob, String> {
+    globset::Glob::new(pattern).map_err(|e| match glob_error_column(pattern, e.kind()) {
+        Some(col) => format!("'{}' (column {}): {}", pattern, col, e.kind()),
+        None => format!("'{}': {}", pattern, e.kind()),
+    })
+}
+
+fn glob_error_column(pattern: &str, kind: &globset::ErrorKind) -> Option<usize> {
+    use globset::ErrorKind;
+    let chars: Vec<char> = pattern.chars().collect();
+    // Start of the open `[...]` class, and of every open `{...}` group.
+    let mut class: Option<usize> = None;
+    let mut braces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(start) = class {
+            // A `]` right after `[`, `[!` or `[^` is a literal.
+            let first = start + 1 + usize::from(matches!(chars.get(start + 1), Some('!' | '^')));
+            if c == ']' && i > first {
+                class = None;
+            }
+        } else {
+            match c {
+                '\\' => i += 1,
+                '[' => class = Some(i),
+                '{' => braces.push(i),
+                '}' if braces.pop().is_none() && *kind == ErrorKind::UnopenedAlternates => {
+                    return Some(i + 1)
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    match kind {
+        ErrorKind::UnclosedClass => class.map(|i| i + 1),
+        ErrorKind::UnclosedAlternates => braces.last().map(|i| i + 1),
+        ErrorKind::DanglingEscape => Some(chars.len()),
+        ErrorKind::InvalidRange(a, b) => pattern
+            .find(&format!("{}-{}", a, b))
+            .map(|byte| pattern[..byte].chars().count() + 1),
+        _ => None,
     }
 }
 
@@ -45,4 +371,199 @@ run = "echo ok"
         let cfg: Config = toml::from_str(toml).unwrap();
         assert!(cfg.task.is_some());
     }
+
+    #[test]
+    fn parse_fields() {
+        // A line of TOML and what it should parse into.
+        type Case<T> = (&'static str, fn(&T) -> bool);
+
+        let task_cases: &[Case<TaskConfig>] = &[
+            ("run = \"cargo test\"", |t| {
+                t.run == RunConfig::Line("cargo test".into())
+            }),
+            ("run = [\"cargo\", \"test\"]", |t| {
+                t.run == RunConfig::Argv(vec!["cargo".into(), "test".into()])
+            }),
+            ("shell = \"none\"", |t| t.shell == Some(Shell::None)),
+            ("env = { PORT = \"8080\" }", |t| {
+                t.env.as_ref().unwrap()["PORT"] == "8080"
+            }),
+            ("per_file = true", |t| t.per_file == Some(true)),
+            ("on_busy = \"restart\"", |t| {
+                t.on_busy == Some(OnBusy::Restart)
+            }),
+            (
+                "before = [\"rm -rf .cache\", [\"mkdir\", \".cache\"]]",
+                |t| {
+                    t.before.as_deref()
+                        == Some(&[
+                            RunConfig::Line("rm -rf .cache".into()),
+                            RunConfig::Argv(vec!["mkdir".into(), ".cache".into()]),
+                        ])
+                },
+            ),
+            ("restart_on_exit = \"on-failure\"", |t| {
+                t.restart_on_exit == Some(RestartOnExit::OnFailure)
+            }),
+            ("max_retries = 3", |t| t.max_retries == Some(3)),
+            ("stop_signal = \"SIGINT\"", |t| {
+                t.stop_signal == Some(StopSignal::Int)
+            }),
+            ("stop_signal = \" sigusr1\"", |t| {
+                t.stop_signal == Some(StopSignal::Usr1)
+            }),
+            ("stop_signal = \"term\"", |t| {
+                t.stop_signal == Some(StopSignal::Term)
+            }),
+        ];
+        for (line, check) in task_cases {
+            let run = if line.starts_with("run ") {
+                ""
+            } else {
+                "run = \"true\"\n"
+            };
+            let toml = format!("[[task]]\nname = \"t\"\nwatch = []\n{run}{line}\n");
+            let cfg: Config = toml::from_str(&toml).unwrap_or_else(|e| panic!("{line}: {e}"));
+            assert!(check(&cfg.task.unwrap()[0]), "{line}");
+        }
+
+        let global_cases: &[Case<GlobalConfig>] = &[
+            ("shell = \"bash\"", |g| g.shell == Some(Shell::Bash)),
+            ("env_file = \".env\"", |g| {
+                g.env_file == Some(PathBuf::from(".env"))
+            }),
+        ];
+        for (line, check) in global_cases {
+            let cfg: Config = toml::from_str(&format!("[global]\n{line}\n"))
+                .unwrap_or_else(|e| panic!("{line}: {e}"));
+            assert!(check(&cfg.global.unwrap()), "{line}");
+        }
+
+        for line in [
+            "stop_signal = \"SIGFOO\"",
+            "shell = \"fish\"",
+            "on_busy = \"drop\"",
+        ] {
+            let toml = format!("[[task]]\nname = \"t\"\nwatch = []\nrun = \"true\"\n{line}\n");
+            assert!(toml::from_str::<Config>(&toml).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn parse_output() {
+        let toml = r#"[global]
+output = "inherit"
+
+[[task]]
+name = "server"
+watch = ["src/**"]
+run = "cargo run"
+output = "prefix"
+"#;
+        let cfg = toml::from_str::<Config>(toml).unwrap();
+        assert_eq!(cfg.global.unwrap().output, Some(OutputMode::Inherit));
+        assert_eq!(cfg.task.unwrap()[0].output, Some(OutputMode::Prefix));
+        assert!(toml::from_str::<Config>(&toml.replace("\"prefix\"", "\"tty\"")).is_err());
+    }
+
+    #[test]
+    fn parse_log_file() {
+        let toml = r#"[global]
+log_file = "logs/{name}.log"
+log_keep = 2
+
+[[task]]
+name = "server"
+watch = ["src/**"]
+run = "cargo run"
+log_max_size = 1048576
+"#;
+        let cfg = toml::from_str::<Config>(toml).unwrap();
+        let global = cfg.global.unwrap();
+        assert_eq!(global.log_file.as_deref(), Some("logs/{name}.log"));
+        assert_eq!(global.log_keep, Some(2));
+        let task = &cfg.task.unwrap()[0];
+        assert_eq!(task.log_file, None);
+        assert_eq!(task.log_max_size, Some(1048576));
+    }
+
+    #[test]
+    fn parse_glob_columns() {
+        let column = |pat: &str| parse_glob(pat).unwrap_err();
+        assert!(parse_glob("src/**/*.{ts,tsx}").is_ok());
+        assert_eq!(
+            column("src/[ab"),
+            "'src/[ab' (column 5): unclosed character class; missing ']'"
+        );
+        assert!(column("a/{b,c").contains("(column 3)"));
+        assert!(column("x/[z-a]").contains("(column 4)"));
+        assert!(column("a}").contains("(column 2)"));
+    }
+
+    #[test]
+    fn check_dependencies() {
+        let parse = |deps: &str| {
+            let toml = format!(
+                r#"[[task]]
+name = "build"
+watch = []
+run = "cargo build"
+depends_on = {deps}
+
+[[task]]
+name = "serve"
+watch = []
+run = "cargo run"
+depends_on = ["build"]
+"#
+            );
+            toml::from_str::<Config>(&toml)
+                .unwrap()
+                .check_dependencies()
+        };
+        assert!(parse("[]").is_ok());
+        let err = parse(r#"["serve"]"#).unwrap_err().to_string();
+        assert_eq!(err, "dependency cycle: build -> serve -> build");
+        let err = parse(r#"["lint"]"#).unwrap_err().to_string();
+        assert!(err.contains("unknown task 'lint'"), "{err}");
+    }
+
+    #[test]
+    fn resolve_relative_to_config() {
+        let cfg = Config {
+            global: None,
+            task: None,
+            base_dir: Some(PathBuf::from("/repo")),
+        };
+        assert_eq!(
+            cfg.resolve_path(Path::new("services/api")),
+            PathBuf::from("/repo/services/api")
+        );
+        assert_eq!(cfg.resolve_path(Path::new("/abs")), PathBuf::from("/abs"));
+    }
+
+    #[test]
+    fn discover_config() {
+        let dir = std::env::temp_dir().join(format!("anymon-discover-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = dir.join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("app/src")).unwrap();
+        std::fs::write(dir.join("Anymon.toml"), "").unwrap();
+
+        // Files above the repository don't count.
+        assert_eq!(Config::discover(&repo.join("app/src")), None);
+        std::fs::write(repo.join(".anymon.toml"), "").unwrap();
+        assert_eq!(
+            Config::discover(&repo.join("app/src")),
+            Some(repo.join(".anymon.toml"))
+        );
+        std::fs::write(repo.join("app/anymon.toml"), "").unwrap();
+        std::fs::write(repo.join("app/Anymon.toml"), "").unwrap();
+        assert_eq!(
+            Config::discover(&repo.join("app/src")),
+            Some(repo.join("app/Anymon.toml"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
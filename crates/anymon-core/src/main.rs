@@ -4,9 +4,10 @@ use reqwest::blocking as reqwest_blocking;
 use std::env;
 use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use anymon_config::Config as AnymonConfig;
-use anymon_runner::pref;
+use anymon_runner::{pref, pref_task};
 
 #[derive(Parser, Debug)]
 #[command(name = "anymon")]
@@ -19,7 +20,7 @@ struct Cli {
     #[arg(long, value_name = "PATH", global = true)]
     watch: Option<Vec<String>>,
 
-    /// Config file (TOML only)
+    /// Config file (TOML only); defaults to the nearest Anymon.toml
     #[arg(long, value_name = "FILE", global = true)]
     config: Option<String>,
 
@@ -34,17 +35,31 @@ struct Cli {
     /// Run once and exit
     #[arg(long, global = true, default_value_t = false)]
     once: bool,
+
+    /// Single-key controls in watch mode (r, 1-9, c, p, q)
+    #[arg(long, global = true, default_value_t = false)]
+    keys: bool,
+
+    /// Full-screen dashboard in watch mode (one pane per task)
+    #[arg(long, global = true, default_value_t = false)]
+    ui: bool,
+
+    /// Pass task output straight through instead of prefixing each line
+    #[arg(long, global = true, default_value_t = false)]
+    raw: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Run a command once (no shell interpretation)
+    /// Run a command once (shell-style quoting, no shell unless needed)
     Run {
         #[arg(value_name = "COMMAND")]
         command: String,
     },
     /// Watch files based on TOML config and run tasks on change
     Watch,
+    /// Validate the config file and report every problem in it
+    Check,
     /// Debug mode (extra output)
     Debug,
     /// Update anymon to the latest version
@@ -55,19 +70,47 @@ enum Commands {
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    let config = if let Some(config_path) = &cli.config {
-        if config_path.ends_with(".toml") {
+    // Without --config, commands that use one look for it from the current
+    // directory up.
+    let needs_config = matches!(
+        cli.command,
+        Some(Commands::Watch | Commands::Check | Commands::Debug)
+    );
+    let config_path = match &cli.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None if needs_config => AnymonConfig::discover(&env::current_dir()?),
+        None => None,
+    };
+
+    if let Some(Commands::Check) = &cli.command {
+        let Some(config_path) = &config_path else {
+            eprintln!("{} no config found; pass --config Anymon.toml", pref());
+            std::process::exit(2);
+        };
+        std::process::exit(check_config(config_path));
+    }
+
+    let config = if let Some(config_path) = &config_path {
+        if config_path.extension().is_some_and(|ext| ext == "toml") {
+            if cli.config.is_none() {
+                println!("{} using config {}", pref(), config_path.display());
+            }
             match AnymonConfig::from_toml(config_path) {
                 Ok(cfg) => Some(cfg),
                 Err(e) => {
-                    eprintln!("{} failed to load TOML config: {e}", pref());
-                    None
+                    eprintln!(
+                        "{} failed to load TOML config {}:\n{e:#}",
+                        pref(),
+                        config_path.display()
+                    );
+                    std::process::exit(1);
                 }
             }
         } else {
             eprintln!(
-                "{} unsupported config file format (only TOML allowed): {config_path}",
-                pref()
+                "{} unsupported config file format (only TOML allowed): {}",
+                pref(),
+                config_path.display()
             );
             None
         }
@@ -78,37 +121,75 @@ async fn main() -> Result<()> {
     match &cli.command {
         Some(Commands::Run { command }) => {
             println!("{} run: {}", pref(), command);
-            let mut parts = command.split_whitespace();
-            if let Some(prog) = parts.next() {
-                let args: Vec<&str> = parts.collect();
-                match anymon_shell::run_command(prog, &args) {
-                    Ok(out) => {
-                        if !out.stdout.is_empty() {
-                            print!("{}", out.stdout);
-                        }
-                        if !out.stderr.is_empty() {
-                            eprint!("{}", out.stderr);
-                        }
-                        println!("{} process exited: {}", pref(), out.status);
+            match anymon_shell::run_command_line(command) {
+                Ok(out) => {
+                    if !out.stdout.is_empty() {
+                        print!("{}", out.stdout);
+                    }
+                    if !out.stderr.is_empty() {
+                        eprint!("{}", out.stderr);
                     }
-                    Err(e) => eprintln!("{} failed to run '{}': {}", pref(), command, e),
+                    println!("{} process exited: {}", pref(), out.status);
                 }
-            } else {
-                eprintln!("{} empty command", pref());
+                Err(e) => eprintln!("{} failed to run '{}': {}", pref(), command, e),
             }
         }
         Some(Commands::Watch) => {
             println!("{} watch mode", pref());
             if let Some(cfg) = config {
-                anymon_runner::watch_mode(cfg, cli.watch, cli.debounce, cli.kill_timeout).await?;
+                let result = anymon_runner::watch_mode(
+                    cfg,
+                    cli.watch,
+                    cli.debounce,
+                    cli.kill_timeout,
+                    anymon_runner::WatchOptions {
+                        keys: cli.keys,
+                        ui: cli.ui,
+                        raw: cli.raw,
+                    },
+                )
+                .await;
+                if let Err(e) = result {
+                    eprintln!("{} {e:#}", pref());
+                    std::process::exit(1);
+                }
             } else {
-                eprintln!("{} watch requires --config anymon.toml", pref());
+                eprintln!(
+                    "{} no config found; create Anymon.toml or pass --config",
+                    pref()
+                );
             }
         }
         Some(Commands::Debug) => {
             println!("{} debug mode", pref());
             if let Some(cfg) = &config {
                 println!("{} loaded config: {:#?}", pref(), cfg);
+                for task in cfg.task.iter().flatten() {
+                    let cmd = anymon_runner::Command::from(task.run.clone());
+                    let resolved =
+                        anymon_runner::SpawnOptions::for_task(cfg, task).and_then(|opts| {
+                            let env = anymon_runner::env::resolve(&opts.env)?;
+                            let argv = cmd.argv(opts.shell, &env)?;
+                            Ok((opts, env, argv))
+                        });
+                    match resolved {
+                        Ok((opts, env, argv)) => {
+                            println!(
+                                "{} argv ({}): {:?}",
+                                pref_task(&task.name),
+                                cmd.mode(opts.shell),
+                                argv
+                            );
+                            if let Some(cwd) = &opts.cwd {
+                                println!("{} cwd: {}", pref_task(&task.name), cwd.display());
+                            }
+                            for (key, value) in env.iter() {
+                                println!("{} env: {}={}", pref_task(&task.name), key, value);
+                            }
+                        }
+                        Err(e) => eprintln!("{} invalid task: {}", pref_task(&task.name), e),
+                    }
+                }
             } else {
                 println!("{} no config loaded", pref());
             }
@@ -121,6 +202,8 @@ async fn main() -> Result<()> {
                 }
             });
         }
+        // Handled before the config is loaded.
+        Some(Commands::Check) => {}
         None => {
             println!("{} no command specified. See --help.", pref());
         }
@@ -128,6 +211,34 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Print every problem in the config at `path`; the exit status is 1 if any
+/// of them is an error.
+fn check_config(path: &Path) -> i32 {
+    let report = match anymon_config::check::Report::load(path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{} cannot read {}: {e}", pref(), path.display());
+            return 1;
+        }
+    };
+    eprint!("{}", report.render(anymon_config::check::Severity::Warning));
+    let (errors, warnings) = (report.errors(), report.warnings());
+    if errors == 0 && warnings == 0 {
+        let tasks = report
+            .config
+            .as_ref()
+            .map_or(0, |c| c.task.iter().flatten().count());
+        println!("{} {}: ok, {tasks} task(s)", pref(), path.display());
+    } else {
+        println!(
+            "{} {}: {errors} error(s), {warnings} warning(s)",
+            pref(),
+            path.display()
+        );
+    }
+    i32::from(errors > 0)
+}
+
 fn update_anymon() -> Result<()> {
     println!("{} updating anymon to the latest version...", pref());
     let os = if cfg!(target_os = "windows") {
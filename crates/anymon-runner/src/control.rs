@@ -0,0 +1,124 @@
+//! Commands typed on stdin while watch mode runs.
+
+/// A parsed control command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// Sent to the task loops.
+    Task(TaskCommand),
+    /// Print the state of one task, or of every task.
+    Status(Option<String>),
+    List,
+    Help,
+    Quit,
+}
+
+/// A command for the task named `target`, or for every task if `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskCommand {
+    pub action: TaskAction,
+    pub target: Option<String>,
+}
+
+impl TaskCommand {
+    pub fn applies_to(&self, task: &str) -> bool {
+        self.target.as_deref().is_none_or(|t| t == task)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskAction {
+    /// Stop the process (if running) and start it again.
+    Restart,
+    /// Stop the process and ignore triggers until `start`.
+    Stop,
+    /// Start a stopped task.
+    Start,
+    /// Keep the process as it is but ignore file changes.
+    Pause,
+    Resume,
+}
+
+pub const HELP: &str = "\
+commands:
+  rs, restart [task]   restart a task (all tasks without a name)
+  stop [task]          stop a task and ignore its triggers until `start`
+  start [task]         start a stopped task
+  pause [task]         ignore file changes, leaving the process running
+  resume [task]        react to file changes again
+  status [task]        show what tasks are doing
+  list                 list task names
+  help                 show this help
+  q, quit, exit        stop all tasks and exit";
+
+impl Control {
+    /// Parse a line typed on stdin. Command words are case-insensitive;
+    /// task names are not.
+    pub fn parse(line: &str) -> Result<Control, String> {
+        let mut words = line.split_whitespace();
+        let Some(cmd) = words.next() else {
+            return Err("empty command".to_string());
+        };
+        let target = words.next().map(str::to_string);
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected argument '{}'", extra));
+        }
+        let task = |action| {
+            Control::Task(TaskCommand {
+                action,
+                target: target.clone(),
+            })
+        };
+        let no_target = |control: Control| match &target {
+            Some(arg) => Err(format!("'{}' takes no argument, got '{}'", cmd, arg)),
+            None => Ok(control),
+        };
+        match cmd.to_lowercase().as_str() {
+            "rs" | "restart" => Ok(task(TaskAction::Restart)),
+            "stop" => Ok(task(TaskAction::Stop)),
+            "start" => Ok(task(TaskAction::Start)),
+            "pause" => Ok(task(TaskAction::Pause)),
+            "resume" => Ok(task(TaskAction::Resume)),
+            "status" => Ok(Control::Status(target.clone())),
+            "list" | "ls" => no_target(Control::List),
+            "help" | "h" | "?" => no_target(Control::Help),
+            "q" | "quit" | "exit" => no_target(Control::Quit),
+            _ => Err(format!(
+                "unknown command '{}' (type 'help' for a list)",
+                cmd
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            Control::parse("rs"),
+            Ok(Control::Task(TaskCommand {
+                action: TaskAction::Restart,
+                target: None
+            }))
+        );
+        assert_eq!(
+            Control::parse("  PAUSE api "),
+            Ok(Control::Task(TaskCommand {
+                action: TaskAction::Pause,
+                target: Some("api".into())
+            }))
+        );
+        assert_eq!(
+            Control::parse("status web"),
+            Ok(Control::Status(Some("web".into())))
+        );
+        assert_eq!(Control::parse("q"), Ok(Control::Quit));
+        assert!(Control::parse("list all").is_err());
+        assert!(Control::parse("rs a b").is_err());
+        assert!(Control::parse("frobnicate")
+            .unwrap_err()
+            .contains("unknown command"));
+    }
+}
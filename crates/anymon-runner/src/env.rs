@@ -0,0 +1,212 @@
+//! Environment variables passed to task processes: `env` tables and
+//! `env_file` (.env) files from the config.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// One source of variables. Later layers override earlier ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvLayer {
+    /// A `.env` file, read every time a process is spawned.
+    File(PathBuf),
+    /// Variables from an `env` table.
+    Vars(BTreeMap<String, String>),
+}
+
+/// A variable from a `.env` file or an `env` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvEntry {
+    pub key: String,
+    pub value: String,
+    /// Single-quoted in a `.env` file: used as written, without `${VAR}`
+    /// interpolation.
+    pub literal: bool,
+}
+
+/// Resolve `layers` into the variables to set on the child. `${VAR}` in
+/// values is replaced with the variable resolved so far or, failing that,
+/// from anymon's own environment. Single-quoted `.env` values are kept as
+/// written.
+pub fn resolve(layers: &[EnvLayer]) -> std::io::Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    for layer in layers {
+        let entries = match layer {
+            EnvLayer::File(path) => read_env_file(path)?,
+            EnvLayer::Vars(map) => map
+                .iter()
+                .map(|(key, value)| EnvEntry {
+                    key: key.clone(),
+                    value: value.clone(),
+                    literal: false,
+                })
+                .collect(),
+        };
+        for entry in entries {
+            let value = if entry.literal {
+                entry.value
+            } else {
+                interpolate(&entry.value, |name| {
+                    vars.get(name).cloned().or_else(|| std::env::var(name).ok())
+                })
+            };
+            vars.insert(entry.key, value);
+        }
+    }
+    Ok(vars)
+}
+
+fn read_env_file(path: &Path) -> std::io::Result<Vec<EnvEntry>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        std::io::Error::new(e.kind(), format!("env_file {}: {}", path.display(), e))
+    })?;
+    parse_env_file(&content).map_err(|(line, msg)| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("env_file {}:{}: {}", path.display(), line, msg),
+        )
+    })
+}
+
+/// Parse `.env` content: `KEY=VALUE` lines with optional `export`, `#`
+/// comments, and single (literal) or double (with `\n`, `\"` escapes)
+/// quoted values. Errors carry the 1-based line number.
+pub fn parse_env_file(content: &str) -> Result<Vec<EnvEntry>, (usize, String)> {
+    let mut entries = Vec::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            return Err((line_no, "expected KEY=VALUE".to_string()));
+        };
+        let key = key.trim();
+        let valid_key = key
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            return Err((line_no, format!("invalid variable name '{}'", key)));
+        }
+        let value = value.trim();
+        let literal = value.starts_with('\'');
+        let value = if let Some(rest) = value.strip_prefix('\'') {
+            match rest.find('\'') {
+                Some(end) => rest[..end].to_string(),
+                None => return Err((line_no, "unterminated single quote".to_string())),
+            }
+        } else if let Some(rest) = value.strip_prefix('"') {
+            let mut out = String::new();
+            let mut chars = rest.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => out.push('\n'),
+                        Some('t') => out.push('\t'),
+                        Some(c) => out.push(c),
+                        None => return Err((line_no, "trailing backslash".to_string())),
+                    },
+                    Some(c) => out.push(c),
+                    None => return Err((line_no, "unterminated double quote".to_string())),
+                }
+            }
+            out
+        } else {
+            // Unquoted values end at an inline ` #` comment.
+            match value.find(" #") {
+                Some(end) => value[..end].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        entries.push(EnvEntry {
+            key: key.to_string(),
+            value,
+            literal,
+        });
+    }
+    Ok(entries)
+}
+
+/// Replace every `${VAR}` in `value` using `lookup`; unknown variables
+/// become empty. A `$` not followed by `{` is kept as-is.
+fn interpolate<F>(value: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                out.push_str(&lookup(&after[..end]).unwrap_or_default());
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_file() {
+        let content = "# comment\nexport A=1\nB = 'x y' \nC=\"line\\nnext\"\nD=plain # note\n\n";
+        let entries = parse_env_file(content).unwrap();
+        let entry = |key: &str, value: &str, literal| EnvEntry {
+            key: key.to_string(),
+            value: value.to_string(),
+            literal,
+        };
+        assert_eq!(
+            entries,
+            [
+                entry("A", "1", false),
+                entry("B", "x y", true),
+                entry("C", "line\nnext", false),
+                entry("D", "plain", false),
+            ]
+        );
+        assert_eq!(parse_env_file("A=1\nnot a pair").unwrap_err().0, 2);
+    }
+
+    #[test]
+    fn later_layers_override_and_interpolate() {
+        let global = EnvLayer::Vars(BTreeMap::from([
+            ("LEVEL".to_string(), "info".to_string()),
+            ("NAME".to_string(), "app".to_string()),
+        ]));
+        let task = EnvLayer::Vars(BTreeMap::from([
+            ("LEVEL".to_string(), "debug".to_string()),
+            ("TAG".to_string(), "${NAME}-${LEVEL}".to_string()),
+        ]));
+        let vars = resolve(&[global, task]).unwrap();
+        assert_eq!(vars["LEVEL"], "debug");
+        assert_eq!(vars["TAG"], "app-debug");
+    }
+
+    #[test]
+    fn single_quoted_values_are_literal() {
+        let dir = std::env::temp_dir().join(format!("anymon-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(".env");
+        std::fs::write(&file, "A=1\nB='lit ${A}'\nC=\"dq ${A}\"\nD=${A}\n").unwrap();
+        let vars = resolve(&[EnvLayer::File(file)]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vars["B"], "lit ${A}");
+        assert_eq!(vars["C"], "dq 1");
+        assert_eq!(vars["D"], "1");
+    }
+}
@@ -0,0 +1,183 @@
+//! File events broadcast by the watcher and the debounced batches that
+//! trigger task runs.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A filesystem change seen by the watcher.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEvent {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Remove,
+    Rename,
+    Other,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Remove => "remove",
+            ChangeKind::Rename => "rename",
+            ChangeKind::Other => "other",
+        }
+    }
+}
+
+impl From<&notify::EventKind> for ChangeKind {
+    fn from(kind: &notify::EventKind) -> Self {
+        use notify::event::ModifyKind;
+        use notify::EventKind;
+        match kind {
+            EventKind::Create(_) => ChangeKind::Create,
+            EventKind::Modify(ModifyKind::Name(_)) => ChangeKind::Rename,
+            EventKind::Modify(_) => ChangeKind::Modify,
+            EventKind::Remove(_) => ChangeKind::Remove,
+            _ => ChangeKind::Other,
+        }
+    }
+}
+
+/// The changes collected during one debounce window.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeBatch {
+    /// Changed paths in the order they were first seen, without duplicates.
+    pub paths: Vec<PathBuf>,
+    kinds: Vec<ChangeKind>,
+}
+
+impl ChangeBatch {
+    pub fn push(&mut self, event: &FileEvent) {
+        if !self.paths.contains(&event.path) {
+            self.paths.push(event.path.clone());
+        }
+        if !self.kinds.contains(&event.kind) {
+            self.kinds.push(event.kind);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Fold `other` into this batch.
+    pub fn merge(&mut self, other: ChangeBatch) {
+        for path in other.paths {
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        }
+        for kind in other.kinds {
+            if !self.kinds.contains(&kind) {
+                self.kinds.push(kind);
+            }
+        }
+    }
+
+    /// A batch holding only `path`, keeping this batch's event kind.
+    pub fn only(&self, path: &Path) -> ChangeBatch {
+        ChangeBatch {
+            paths: vec![path.to_path_buf()],
+            kinds: self.kinds.clone(),
+        }
+    }
+
+    /// The kind shared by every event in the batch, or "mixed".
+    pub fn kind(&self) -> &'static str {
+        match self.kinds.as_slice() {
+            [kind] => kind.as_str(),
+            [] => "none",
+            _ => "mixed",
+        }
+    }
+}
+
+/// How a task's run ended, broadcast so that dependent tasks can react.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskOutcome {
+    pub task: String,
+    pub success: bool,
+}
+
+/// Why a task is being (re)started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// First start when watch mode begins.
+    Initial,
+    /// Restart requested from the control channel.
+    Manual,
+    /// A debounced batch of file changes.
+    Changes(ChangeBatch),
+    /// A task this one depends on (named here) finished successfully.
+    Dependency(String),
+}
+
+impl Trigger {
+    /// The changed paths behind this trigger (empty unless it is `Changes`).
+    pub fn paths(&self) -> &[PathBuf] {
+        match self {
+            Trigger::Changes(batch) => &batch.paths,
+            _ => &[],
+        }
+    }
+
+    /// Variables describing the trigger, passed to the spawned command:
+    /// `ANYMON_CHANGED_PATHS` (newline-separated), `ANYMON_CHANGED_COUNT` and
+    /// `ANYMON_EVENT_KIND`.
+    pub fn env(&self) -> BTreeMap<String, String> {
+        let paths = self.paths();
+        let kind = match self {
+            Trigger::Initial => "initial",
+            Trigger::Manual => "manual",
+            Trigger::Changes(batch) => batch.kind(),
+            Trigger::Dependency(_) => "dependency",
+        };
+        let joined: Vec<String> = paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        BTreeMap::from([
+            ("ANYMON_CHANGED_PATHS".to_string(), joined.join("\n")),
+            ("ANYMON_CHANGED_COUNT".to_string(), paths.len().to_string()),
+            ("ANYMON_EVENT_KIND".to_string(), kind.to_string()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_env() {
+        let mut batch = ChangeBatch::default();
+        for (path, kind) in [
+            ("/p/a.rs", ChangeKind::Modify),
+            ("/p/b.rs", ChangeKind::Modify),
+            ("/p/a.rs", ChangeKind::Modify),
+        ] {
+            batch.push(&FileEvent {
+                path: PathBuf::from(path),
+                kind,
+            });
+        }
+        let env = Trigger::Changes(batch.clone()).env();
+        assert_eq!(env["ANYMON_CHANGED_PATHS"], "/p/a.rs\n/p/b.rs");
+        assert_eq!(env["ANYMON_CHANGED_COUNT"], "2");
+        assert_eq!(env["ANYMON_EVENT_KIND"], "modify");
+
+        batch.push(&FileEvent {
+            path: PathBuf::from("/p/c.rs"),
+            kind: ChangeKind::Create,
+        });
+        assert_eq!(batch.kind(), "mixed");
+    }
+}
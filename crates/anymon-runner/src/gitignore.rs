@@ -0,0 +1,144 @@
+//! Ignore files (`.gitignore`, `.ignore`, `.anymonignore` and git's global
+//! excludes) applied to file events.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+/// Ignore files read in every directory, highest precedence first.
+pub const IGNORE_FILES: [&str; 3] = [".anymonignore", ".ignore", ".gitignore"];
+
+pub struct IgnoreFiles {
+    roots: Vec<Root>,
+    /// Matchers of each directory, in `IGNORE_FILES` order, loaded on first
+    /// use and dropped when one of its ignore files changes.
+    dirs: HashMap<PathBuf, Vec<Gitignore>>,
+}
+
+struct Root {
+    path: PathBuf,
+    /// Ignore files from here down apply: the enclosing git repository, or
+    /// the watch root itself.
+    top: PathBuf,
+    /// git's global excludes (`core.excludesFile`).
+    global: Gitignore,
+}
+
+impl IgnoreFiles {
+    pub fn new(roots: &[PathBuf]) -> IgnoreFiles {
+        let roots = roots
+            .iter()
+            .map(|path| {
+                let top = path
+                    .ancestors()
+                    .find(|dir| dir.join(".git").exists())
+                    .unwrap_or(path)
+                    .to_path_buf();
+                let (global, _) = GitignoreBuilder::new(&top).build_global();
+                Root {
+                    path: path.clone(),
+                    top,
+                    global,
+                }
+            })
+            .collect();
+        IgnoreFiles {
+            roots,
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Whether `path` is ignored. The deepest ignore file with a matching
+    /// pattern decides, so nested files and `!` negations override their
+    /// parents. Anything inside `.git` is always ignored.
+    pub fn is_ignored(&mut self, path: &Path) -> bool {
+        if path
+            .file_name()
+            .is_some_and(|name| IGNORE_FILES.iter().any(|f| name == *f))
+        {
+            if let Some(dir) = path.parent() {
+                self.dirs.remove(dir);
+            }
+        }
+        let Some(i) = (0..self.roots.len())
+            .filter(|&i| path.starts_with(&self.roots[i].path))
+            .max_by_key(|&i| self.roots[i].path.as_os_str().len())
+        else {
+            return false;
+        };
+        let top = self.roots[i].top.clone();
+        let Ok(rel) = path.strip_prefix(&top) else {
+            return false;
+        };
+        if rel.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+        let is_dir = path.is_dir();
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&top) {
+                break;
+            }
+            for matcher in self.matchers(dir) {
+                match matcher.matched_path_or_any_parents(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        self.roots[i]
+            .global
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    fn matchers(&mut self, dir: &Path) -> &[Gitignore] {
+        self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            IGNORE_FILES
+                .iter()
+                .map(|name| dir.join(name))
+                .filter(|file| file.is_file())
+                .map(|file| {
+                    let mut builder = GitignoreBuilder::new(dir);
+                    builder.add(file);
+                    builder.build().unwrap_or_else(|_| Gitignore::empty())
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn applies_nested_ignore_files() {
+        let dir = std::env::temp_dir().join(format!("anymon-ignore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::create_dir_all(dir.join("src/gen")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join("src/.gitignore"), "!keep.log\n").unwrap();
+        fs::write(dir.join("src/gen/.anymonignore"), "*.rs\n").unwrap();
+
+        let mut ignored = IgnoreFiles::new(std::slice::from_ref(&dir));
+        let mut check = |rel: &str| ignored.is_ignored(&dir.join(rel));
+        assert!(check("target/debug/app"));
+        assert!(check("out.log"));
+        assert!(check("src/other.log"));
+        assert!(!check("src/keep.log"));
+        assert!(check("src/gen/schema.rs"));
+        assert!(!check("src/main.rs"));
+        assert!(check(".git/index"));
+
+        // Edits to ignore files apply to later events.
+        fs::write(dir.join("src/.gitignore"), "main.rs\n").unwrap();
+        check("src/.gitignore");
+        assert!(check("src/main.rs"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Single-keypress controls for watch mode (`--keys`).
+
+/// What a key press does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    RestartAll,
+    /// Restart the task at this index (0-based) in config order.
+    Restart(usize),
+    Clear,
+    TogglePause,
+    Quit,
+}
+
+pub const HELP: &str = "keys: r restart all, 1-9 restart task N, c clear, p pause/resume, q quit";
+
+impl Key {
+    pub fn from_byte(byte: u8) -> Option<Key> {
+        match byte {
+            b'r' | b'R' => Some(Key::RestartAll),
+            b'1'..=b'9' => Some(Key::Restart((byte - b'1') as usize)),
+            b'c' | b'C' => Some(Key::Clear),
+            b'p' | b'P' => Some(Key::TogglePause),
+            b'q' | b'Q' => Some(Key::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Puts the terminal into cbreak mode (keys are delivered without Enter and
+/// not echoed) while alive. Output processing and signal keys are left
+/// alone, so log lines render normally and Ctrl-C still works.
+#[cfg(unix)]
+pub struct RawMode {
+    original: libc::termios,
+}
+
+/// Why [`RawMode::enable`] returned `None`.
+#[cfg(unix)]
+pub const UNAVAILABLE: &str = "stdin is not a terminal";
+#[cfg(not(unix))]
+pub const UNAVAILABLE: &str = "single-key controls are not supported on this platform";
+
+#[cfg(unix)]
+impl RawMode {
+    /// Returns `None` if stdin is not a terminal.
+    pub fn enable() -> Option<RawMode> {
+        use std::io::IsTerminal;
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        // SAFETY: termios is plain data; tcgetattr fills it in before use.
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            let original = termios;
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return None;
+            }
+            Some(RawMode { original })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `enable`.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Single-key input is only implemented for unix terminals.
+#[cfg(not(unix))]
+pub struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_keys() {
+        assert_eq!(Key::from_byte(b'r'), Some(Key::RestartAll));
+        assert_eq!(Key::from_byte(b'1'), Some(Key::Restart(0)));
+        assert_eq!(Key::from_byte(b'9'), Some(Key::Restart(8)));
+        assert_eq!(Key::from_byte(b'0'), None);
+        assert_eq!(Key::from_byte(b'Q'), Some(Key::Quit));
+        assert_eq!(Key::from_byte(b'\n'), None);
+    }
+}
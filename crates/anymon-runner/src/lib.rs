@@ -1,113 +1,549 @@
 use anyhow::Result;
+pub use anymon_config::StopSignal;
+use anymon_config::{OnBusy, OutputMode, RestartOnExit, Shell};
 use colored::Colorize;
 use globset::GlobSet;
 use notify::Event;
 use notify::Watcher;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub mod control;
+pub mod env;
+pub mod events;
+pub mod gitignore;
+pub mod keys;
+pub mod logfile;
+pub mod output;
+pub mod state;
+#[cfg(unix)]
+pub mod terminal;
+pub mod ui;
+
+use control::{Control, TaskAction, TaskCommand};
+use env::EnvLayer;
+use events::{ChangeBatch, ChangeKind, FileEvent, TaskOutcome, Trigger};
+use state::{Phase, SharedState, TaskState};
 
 #[derive(Clone)]
 pub struct TaskSpec {
     pub name: String,
-    pub run: String,
+    pub run: Command,
     pub restart: bool,
+    /// Run the command once per changed file instead of once per batch.
+    pub per_file: bool,
+    /// For run-to-completion tasks (`restart = false` or `per_file`): what
+    /// to do with changes that arrive mid-run.
+    pub on_busy: OnBusy,
+    /// Tasks whose successful runs (re)start this one.
+    pub depends_on: Vec<String>,
+    pub hooks: Hooks,
+    /// Automatic restarts after the process exits on its own.
+    pub restart_on_exit: RestartOnExit,
+    /// Delay before the first automatic restart, doubled for each further
+    /// one in a row.
+    pub restart_delay: std::time::Duration,
+    pub max_retries: u32,
     pub globset: GlobSet,
+    /// `ignore` globs and `!` entries of `watch`.
+    pub ignore: GlobSet,
     pub roots: Vec<PathBuf>,
+    pub stop_signal: StopSignal,
+    /// Whether output is captured and prefixed or passed through.
+    pub output: OutputMode,
+    /// Where captured output is also written.
+    pub log: Option<Arc<logfile::LogFile>>,
+    pub spawn: SpawnOptions,
 }
 
-pub fn pref() -> String {
-    "[anymon]".cyan().bold().to_string()
+impl TaskSpec {
+    /// Whether a change to `path` should trigger this task: it matches one
+    /// of the watch globs (relative to a root) and none of its exclusions, or
+    /// is one of its env files. A task without watch globs matches nothing.
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.spawn.env_files().any(|f| f == path) {
+            return true;
+        }
+        let relative = |set: &GlobSet| {
+            self.roots.iter().any(|root| {
+                path.strip_prefix(root)
+                    .map(|rel| set.is_match(rel))
+                    .unwrap_or(false)
+            })
+        };
+        if self.ignore.is_match(path) || relative(&self.ignore) {
+            return false;
+        }
+        relative(&self.globset)
+    }
+
+    /// Capture this task's output into `tx` (and its log file).
+    fn output_sink(&self, tx: &output::OutputTx) -> output::OutputSink {
+        output::OutputSink {
+            task: self.name.clone(),
+            tx: tx.clone(),
+            log: self.log.clone(),
+        }
+    }
+
+    /// Spawn the task's command with its placeholders filled in from the
+    /// trigger, which is also described through `ANYMON_*` environment
+    /// variables.
+    pub fn spawn(&self, trigger: &Trigger) -> std::io::Result<tokio::process::Child> {
+        self.spawn_with(&self.run, trigger, None)
+    }
+
+    /// Spawn `cmd` (the task's command or one of its hooks) the same way,
+    /// adding `ANYMON_EXIT_CODE` once the main command has exited.
+    pub fn spawn_with(
+        &self,
+        cmd: &Command,
+        trigger: &Trigger,
+        exit: Option<std::process::ExitStatus>,
+    ) -> std::io::Result<tokio::process::Child> {
+        let mut opts = self.spawn.clone();
+        opts.extra_env.extend(trigger.env());
+        if let Some(status) = exit {
+            opts.extra_env
+                .insert("ANYMON_EXIT_CODE".to_string(), exit_code(status));
+        }
+        try_spawn(&cmd.expand(trigger.paths(), opts.shell), &opts)
+    }
 }
 
-pub fn pref_task(name: &str) -> String {
-    let left: colored::ColoredString = "[anymon]".cyan().bold();
-    let right: colored::ColoredString = format!("[{}]", name).black().bold();
-    format!("{} {}:", left, right)
+/// Commands run around a task's main command.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub before: Vec<Command>,
+    pub after: Vec<Command>,
+    pub on_success: Vec<Command>,
+    pub on_failure: Vec<Command>,
 }
 
-pub fn try_spawn(cmd: &str) -> std::io::Result<tokio::process::Child> {
-    use std::path::{Path, PathBuf};
+impl Hooks {
+    pub fn from_task(t: &anymon_config::TaskConfig) -> Self {
+        let commands = |list: &Option<Vec<anymon_config::RunConfig>>| {
+            list.iter().flatten().cloned().map(Command::from).collect()
+        };
+        Hooks {
+            before: commands(&t.before),
+            after: commands(&t.after),
+            on_success: commands(&t.on_success),
+            on_failure: commands(&t.on_failure),
+        }
+    }
+}
+
+/// How a process ended, for log lines: its exit code or the signal that
+/// killed it.
+fn exit_reason(status: std::process::ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => status.to_string(),
+    }
+}
+
+/// The exit code as a shell reports it: 128 + the signal number for
+/// processes killed by a signal.
+fn exit_code(status: std::process::ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return (128 + sig).to_string();
+        }
+    }
+    status.code().map(|c| c.to_string()).unwrap_or_default()
+}
 
-    fn find_executable(name: &str) -> Option<PathBuf> {
-        // If name already contains a path separator, treat it as a path and
-        // return it if it exists and is a file.
-        let p = Path::new(name);
-        if p.components().count() > 1 || name.contains(std::path::MAIN_SEPARATOR) {
-            if p.exists() {
-                return Some(p.to_path_buf());
+/// Options applied to every process a task spawns.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    pub shell: Shell,
+    /// Working directory for the process (inherited when `None`).
+    pub cwd: Option<PathBuf>,
+    /// Extra environment, lowest precedence first.
+    pub env: Vec<EnvLayer>,
+    /// Variables set as-is on top of `env` (no `${VAR}` interpolation).
+    pub extra_env: BTreeMap<String, String>,
+    /// Capture stdout and stderr line by line instead of inheriting them.
+    pub output: Option<output::OutputSink>,
+}
+
+impl SpawnOptions {
+    /// Build the spawn options for task `t`: its `shell`, `cwd`, `env` and
+    /// `env_file`, falling back to the `[global]` settings.
+    pub fn for_task(
+        cfg: &anymon_config::Config,
+        t: &anymon_config::TaskConfig,
+    ) -> anyhow::Result<Self> {
+        let global = cfg.global.as_ref();
+
+        let cwd = t.cwd.as_ref().map(|d| cfg.resolve_path(d));
+        if let Some(dir) = &cwd {
+            if !dir.is_dir() {
+                anyhow::bail!(
+                    "task '{}': cwd {} is not a directory",
+                    t.name,
+                    dir.display()
+                );
             }
-            return None;
         }
 
-        let paths = std::env::var_os("PATH")?;
-        #[cfg(windows)]
-        let splits = std::env::split_paths(&paths);
-        #[cfg(not(windows))]
-        let splits = std::env::split_paths(&paths);
+        // global env_file < global env < task env_file < task env
+        let mut env = Vec::new();
+        if let Some(g) = global {
+            if let Some(file) = &g.env_file {
+                env.push(EnvLayer::File(cfg.resolve_path(file)));
+            }
+            if let Some(vars) = &g.env {
+                env.push(EnvLayer::Vars(vars.clone()));
+            }
+        }
+        if let Some(file) = &t.env_file {
+            env.push(EnvLayer::File(cfg.resolve_path(file)));
+        }
+        if let Some(vars) = &t.env {
+            env.push(EnvLayer::Vars(vars.clone()));
+        }
 
-        #[cfg(windows)]
-        let pathext = std::env::var_os("PATHEXT").unwrap_or_else(|| ".EXE;.CMD;.BAT;.COM".into());
-        #[cfg(windows)]
-        let exts: Vec<String> = pathext
-            .to_string_lossy()
-            .split(';')
-            .map(|s| s.to_string())
-            .collect();
-
-        for dir in splits {
-            #[cfg(windows)]
-            {
-                for ext in &exts {
-                    let candidate = dir.join(format!("{}{}", name, ext));
-                    if candidate.exists() {
-                        return Some(candidate);
+        Ok(SpawnOptions {
+            shell: t.shell.or(global.and_then(|g| g.shell)).unwrap_or_default(),
+            cwd,
+            env,
+            extra_env: BTreeMap::new(),
+            output: None,
+        })
+    }
+
+    /// The `.env` files this task reads.
+    pub fn env_files(&self) -> impl Iterator<Item = &Path> {
+        self.env.iter().filter_map(|layer| match layer {
+            EnvLayer::File(path) => Some(path.as_path()),
+            EnvLayer::Vars(_) => None,
+        })
+    }
+}
+
+/// What a task runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// A command line, split with shell-style quoting when spawned.
+    Line(String),
+    /// An argv array, spawned exactly as given.
+    Argv(Vec<String>),
+}
+
+impl Command {
+    /// The argv that will be spawned for this command with the given
+    /// `shell` setting. Argv arrays are always returned unchanged; `$VAR` in
+    /// command lines expands from `env` first, then anymon's environment.
+    pub fn argv(
+        &self,
+        shell: Shell,
+        env: &BTreeMap<String, String>,
+    ) -> std::io::Result<Vec<String>> {
+        let line = match self {
+            Command::Argv(argv) => return Ok(argv.clone()),
+            Command::Line(line) => line,
+        };
+        match shell {
+            Shell::Bash | Shell::Sh | Shell::Pwsh => Ok(shell_argv(shell, line)),
+            Shell::Auto | Shell::None => {
+                match anymon_shell::parse_command_line_with(line, |name| {
+                    env.get(name).cloned().or_else(|| std::env::var(name).ok())
+                })? {
+                    anymon_shell::CommandLine::Argv(words) => Ok(words),
+                    anymon_shell::CommandLine::Shell if shell == Shell::Auto => {
+                        Ok(shell_argv(shell, line))
                     }
+                    anymon_shell::CommandLine::Shell => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "command uses shell syntax (pipes, redirects, ...) but shell = \"none\"",
+                    )),
                 }
             }
-            #[cfg(not(windows))]
-            {
-                let candidate = dir.join(name);
-                if candidate.exists() {
-                    return Some(candidate);
+        }
+    }
+
+    /// Short description of how this command gets spawned with `shell`.
+    pub fn mode(&self, shell: Shell) -> String {
+        let line = match self {
+            Command::Argv(_) => return "argv, no shell".to_string(),
+            Command::Line(line) => line,
+        };
+        match shell {
+            Shell::None => "direct, no shell".to_string(),
+            Shell::Auto => {
+                let platform = shell_argv(Shell::Auto, line)[..2].join(" ");
+                match anymon_shell::parse_command_line(line) {
+                    Ok(anymon_shell::CommandLine::Shell) => format!("{} (shell syntax)", platform),
+                    _ => format!("direct, {} if not found", platform),
                 }
             }
+            _ => shell_argv(shell, line)[..2].join(" "),
         }
-        None
     }
+}
+
+/// Placeholders substituted in `run` with the changed files of a batch.
+const PLACEHOLDERS: [&str; 4] = ["{changed}", "{file}", "{dir}", "{ext}"];
+
+impl Command {
+    /// Whether the command uses any of `{changed}`, `{file}`, `{dir}` or
+    /// `{ext}`.
+    pub fn has_placeholders(&self) -> bool {
+        let uses = |s: &str| PLACEHOLDERS.iter().any(|p| s.contains(p));
+        match self {
+            Command::Line(line) => uses(line),
+            Command::Argv(argv) => argv.iter().any(|a| uses(a)),
+        }
+    }
+
+    /// Substitute placeholders: `{changed}` becomes every file in `files`,
+    /// `{file}`, `{dir}` and `{ext}` the first file, its directory and its
+    /// extension. In command lines the values are inserted quoted for whatever
+    /// reads the line when it runs with `shell`; in argv arrays an element
+    /// that is exactly `{changed}` becomes one element per file.
+    pub fn expand(&self, files: &[PathBuf], shell: Shell) -> Command {
+        if !self.has_placeholders() {
+            return self.clone();
+        }
+        let lossy = |p: &Path| p.to_string_lossy().into_owned();
+        let file = files.first();
+        let values: [Vec<String>; 4] = [
+            files.iter().map(|p| lossy(p)).collect(),
+            file.map(|p| lossy(p)).into_iter().collect(),
+            file.and_then(|p| p.parent())
+                .map(lossy)
+                .into_iter()
+                .collect(),
+            file.and_then(|p| p.extension())
+                .map(|e| e.to_string_lossy().into_owned())
+                .into_iter()
+                .collect(),
+        ];
+        let powershell = match self {
+            Command::Line(line) => runs_in_powershell(shell, line),
+            Command::Argv(_) => false,
+        };
+        let substitute = |s: &str, quoted: bool| {
+            let mut out = s.to_string();
+            for (placeholder, vals) in PLACEHOLDERS.iter().zip(values.iter()) {
+                let joined: Vec<String> = if quoted {
+                    vals.iter().map(|v| quote_for(powershell, v)).collect()
+                } else {
+                    vals.clone()
+                };
+                out = out.replace(placeholder, &joined.join(" "));
+            }
+            out
+        };
+        match self {
+            Command::Line(line) => Command::Line(substitute(line, true)),
+            Command::Argv(argv) => Command::Argv(
+                argv.iter()
+                    .flat_map(|arg| match PLACEHOLDERS.iter().position(|p| arg == p) {
+                        Some(i) => values[i].clone(),
+                        None => vec![substitute(arg, false)],
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Whether `line` is read by PowerShell when it runs with `shell`, rather than
+/// by `sh`/`bash` or split by anymon itself.
+fn runs_in_powershell(shell: Shell, line: &str) -> bool {
+    match shell {
+        Shell::Pwsh => true,
+        Shell::Auto if cfg!(windows) => matches!(
+            anymon_shell::parse_command_line(line),
+            Ok(anymon_shell::CommandLine::Shell)
+        ),
+        _ => false,
+    }
+}
 
-    let mut parts = cmd.split_whitespace();
-    if let Some(prog) = parts.next() {
-        let args: Vec<&str> = parts.collect();
+fn quote_for(powershell: bool, word: &str) -> String {
+    if powershell {
+        anymon_shell::quote_powershell(word)
+    } else {
+        anymon_shell::quote(word)
+    }
+}
 
+impl From<anymon_config::RunConfig> for Command {
+    fn from(run: anymon_config::RunConfig) -> Self {
+        match run {
+            anymon_config::RunConfig::Line(line) => Command::Line(line),
+            anymon_config::RunConfig::Argv(argv) => Command::Argv(argv),
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Line(line) => f.write_str(line),
+            Command::Argv(argv) => {
+                let quoted: Vec<String> = argv.iter().map(|a| anymon_shell::quote(a)).collect();
+                f.write_str(&quoted.join(" "))
+            }
+        }
+    }
+}
+
+/// The signal number of `signal`.
+#[cfg(unix)]
+fn raw_signal(signal: StopSignal) -> libc::c_int {
+    match signal {
+        StopSignal::Term => libc::SIGTERM,
+        StopSignal::Int => libc::SIGINT,
+        StopSignal::Hup => libc::SIGHUP,
+        StopSignal::Quit => libc::SIGQUIT,
+        StopSignal::Usr1 => libc::SIGUSR1,
+        StopSignal::Usr2 => libc::SIGUSR2,
+        StopSignal::Kill => libc::SIGKILL,
+    }
+}
+
+pub fn pref() -> String {
+    "[anymon]".cyan().bold().to_string()
+}
+
+pub fn pref_task(name: &str) -> String {
+    let left: colored::ColoredString = "[anymon]".cyan().bold();
+    let right: colored::ColoredString = format!("[{}]", name).color(task_color(name)).bold();
+    format!("{} {}:", left, right)
+}
+
+/// The colour of a task's name, derived from the name so that it is the same
+/// in every run.
+fn task_color(name: &str) -> colored::Color {
+    use colored::Color::*;
+    const COLORS: [colored::Color; 8] = [
+        Green,
+        Yellow,
+        Blue,
+        Magenta,
+        BrightGreen,
+        BrightYellow,
+        BrightBlue,
+        BrightMagenta,
+    ];
+    let hash = name
+        .bytes()
+        .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+    COLORS[hash as usize % COLORS.len()]
+}
+
+/// Print captured task output behind the task's name, stderr to stderr.
+async fn print_output(mut rx: tokio::sync::mpsc::UnboundedReceiver<output::OutputLine>) {
+    while let Some(line) = rx.recv().await {
+        let prefix = pref_task(line.task.as_deref().unwrap_or_default());
+        match line.stream {
+            output::Stream::Stderr => eprintln!("{} {}", prefix, line.text),
+            _ => println!("{} {}", prefix, line.text),
+        }
+    }
+}
+
+/// Create a command for a task. On unix every task runs in its own process
+/// group, so that stopping it also reaches anything it started (e.g. the
+/// server behind `sh -c`). A task with inherited output takes over the
+/// terminal as the foreground group if it can.
+fn new_command<S: AsRef<std::ffi::OsStr>>(
+    program: S,
+    opts: &SpawnOptions,
+    env: &BTreeMap<String, String>,
+) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(program);
+    if let Some(cwd) = &opts.cwd {
+        command.current_dir(cwd);
+    }
+    command.envs(env);
+    if opts.output.is_some() {
+        command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+    }
+    #[cfg(unix)]
+    {
+        let foreground = opts.output.is_none() && terminal::available();
+        command.process_group(0);
+        // SAFETY: only async-signal-safe calls run between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                terminal::reset_signals();
+                if foreground {
+                    terminal::take_terminal();
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(windows)]
+    if opts.output.is_some() {
+        command.creation_flags(0x0000_0200); // CREATE_NEW_PROCESS_GROUP
+    }
+    command
+}
+
+pub fn try_spawn(cmd: &Command, opts: &SpawnOptions) -> std::io::Result<tokio::process::Child> {
+    let mut child = spawn_child(cmd, opts)?;
+    if let Some(sink) = &opts.output {
+        output::capture(&mut child, sink);
+    }
+    Ok(child)
+}
+
+fn spawn_child(cmd: &Command, opts: &SpawnOptions) -> std::io::Result<tokio::process::Child> {
+    let mut env = env::resolve(&opts.env)?;
+    env.extend(opts.extra_env.clone());
+    let argv = cmd.argv(opts.shell, &env)?;
+    if let Some((prog, args)) = argv.split_first() {
         // First try to spawn directly (this will search PATH on most OSes),
         // but if it fails with NotFound, try to resolve via PATH/PATHEXT
         // explicitly (handles cases where extension is needed on Windows).
-        match tokio::process::Command::new(prog).args(&args).spawn() {
+        let not_found = match new_command(prog, opts, &env).args(args).spawn() {
             Ok(child) => return Ok(child),
             Err(e) => {
                 if e.kind() != std::io::ErrorKind::NotFound {
                     return Err(e);
                 }
+                e
             }
-        }
+        };
 
-        if let Some(found) = find_executable(prog) {
-            return tokio::process::Command::new(found).args(&args).spawn();
+        if let Some(found) = anymon_shell::find_executable(prog, opts.cwd.as_deref()) {
+            return new_command(found, opts, &env).args(args).spawn();
         }
 
         // As a last resort, fall back to invoking a platform shell to handle
-        // builtins and shell-specific syntax (quotes, pipes, etc.). This is
-        // only used when the program cannot be resolved as an executable.
-        if cfg!(windows) {
-            return tokio::process::Command::new("powershell")
-                .args(["-NoProfile", "-Command", cmd])
-                .spawn();
-        } else {
-            return tokio::process::Command::new("sh")
-                .arg("-c")
-                .arg(cmd)
-                .spawn();
-        }
+        // builtins (e.g. `export`). This is only used for command lines whose
+        // program cannot be resolved as an executable and when no shell was
+        // configured; argv arrays are always spawned as given. The line is
+        // rebuilt from the words it was split into, quoted for that shell.
+        return match cmd {
+            Command::Line(_) if opts.shell == Shell::Auto => {
+                let words: Vec<String> = (argv.iter())
+                    .map(|word| quote_for(cfg!(windows), word))
+                    .collect();
+                let argv = shell_argv(Shell::Auto, &words.join(" "));
+                println!(
+                    "{} '{}' not found, falling back to {}",
+                    pref(),
+                    prog,
+                    argv[..2].join(" ")
+                );
+                new_command(&argv[0], opts, &env).args(&argv[1..]).spawn()
+            }
+            _ => Err(not_found),
+        };
     }
 
     Err(std::io::Error::new(
@@ -116,27 +552,185 @@ pub fn try_spawn(cmd: &str) -> std::io::Result<tokio::process::Child> {
     ))
 }
 
+/// The interpreter invocation for `line`. `Shell::Auto` picks the platform
+/// shell (`sh` or Windows PowerShell).
+fn shell_argv(shell: Shell, line: &str) -> Vec<String> {
+    let prefix: &[&str] = match shell {
+        Shell::Bash => &["bash", "-c"],
+        Shell::Sh => &["sh", "-c"],
+        Shell::Pwsh => &["pwsh", "-NoProfile", "-Command"],
+        Shell::Auto | Shell::None if cfg!(windows) => &["powershell", "-NoProfile", "-Command"],
+        Shell::Auto | Shell::None => &["sh", "-c"],
+    };
+    prefix
+        .iter()
+        .map(|s| s.to_string())
+        .chain(std::iter::once(line.to_string()))
+        .collect()
+}
+
+/// Stop `child` and everything in its process group by sending `signal`
+/// first and escalating to SIGKILL if anything is still alive after
+/// `kill_timeout` ms. Returns the exit status if one could be collected.
+pub async fn stop_child(
+    name: &str,
+    child: &mut tokio::process::Child,
+    signal: StopSignal,
+    kill_timeout: u64,
+) -> Option<std::process::ExitStatus> {
+    let pid = child.id();
+    let status = stop_process(name, child, signal, kill_timeout).await;
+    // Stopped on purpose: a Ctrl-C stop signal isn't passed on to anymon.
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        terminal::reclaim(pid, None);
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+    status
+}
+
+async fn stop_process(
+    name: &str,
+    child: &mut tokio::process::Child,
+    signal: StopSignal,
+    kill_timeout: u64,
+) -> Option<std::process::ExitStatus> {
+    use std::time::Duration;
+
+    // `id()` is only `None` once the child has already been reaped.
+    let Some(pid) = child.id() else {
+        return child.try_wait().ok().flatten();
+    };
+    if let Ok(Some(status)) = child.try_wait() {
+        // Already exited on its own; clear out anything it left behind.
+        #[cfg(unix)]
+        if group_alive(pid) {
+            signal_group(pid, libc::SIGKILL);
+        }
+        return Some(status);
+    }
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(kill_timeout);
+
+    #[cfg(unix)]
+    let status = {
+        let mut status = None;
+        if signal != StopSignal::Kill {
+            println!("{} sending {}", pref_task(name), signal.name());
+            signal_group(pid, raw_signal(signal));
+            status = match tokio::time::timeout_at(deadline, child.wait()).await {
+                Ok(Ok(s)) => Some(s),
+                Ok(Err(e)) => {
+                    eprintln!("{} wait failed: {}", pref_task(name), e);
+                    None
+                }
+                Err(_) => None,
+            };
+            // The leader may exit before the rest of its group does; give the
+            // stragglers the remainder of the timeout as well.
+            while status.is_some() && group_alive(pid) && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            if status.is_some() && !group_alive(pid) {
+                return status;
+            }
+            println!(
+                "{} still running after {}ms, sending SIGKILL",
+                pref_task(name),
+                kill_timeout
+            );
+        }
+        signal_group(pid, libc::SIGKILL);
+        status
+    };
+    #[cfg(not(unix))]
+    let status = {
+        let _ = (signal, deadline);
+        // taskkill /T terminates the whole process tree, not just the child.
+        let _ = tokio::process::Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .await;
+        None
+    };
+
+    if status.is_some() {
+        return status;
+    }
+    if let Err(e) = child.start_kill() {
+        eprintln!("{} kill failed: {}", pref_task(name), e);
+    }
+    match tokio::time::timeout(Duration::from_millis(kill_timeout), child.wait()).await {
+        Ok(Ok(status)) => Some(status),
+        Ok(Err(e)) => {
+            eprintln!("{} wait failed: {}", pref_task(name), e);
+            None
+        }
+        Err(_) => {
+            println!("{} kill timeout exceeded", pref_task(name));
+            None
+        }
+    }
+}
+
+/// Send `sig` to every process in the group led by `pid`.
+#[cfg(unix)]
+fn signal_group(pid: u32, sig: libc::c_int) -> bool {
+    // SAFETY: tasks are spawned with `process_group(0)`, so the group id is
+    // the child's pid and the group only contains processes it started.
+    unsafe { libc::kill(-(pid as libc::pid_t), sig) == 0 }
+}
+
+/// Whether any process is still alive in the group led by `pid`.
+#[cfg(unix)]
+fn group_alive(pid: u32) -> bool {
+    signal_group(pid, 0)
+}
+
 pub async fn run_once(cmd: &str, _kill_timeout: u64) -> Result<()> {
-    let mut child = match try_spawn(cmd) {
+    #[cfg(unix)]
+    terminal::enable();
+    let mut child = match try_spawn(&Command::Line(cmd.to_string()), &SpawnOptions::default()) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{} process spawn failed: {}", pref(), e);
             return Ok(());
         }
     };
+    let pid = child.id();
     let status = child.wait().await?;
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        terminal::reclaim(pid, Some(status));
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
     println!("{} process exited: {}", pref(), status);
     Ok(())
 }
 
+/// How watch mode uses the terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WatchOptions {
+    /// Read single-key controls instead of line commands (`--keys`).
+    pub keys: bool,
+    /// Show the full-screen dashboard (`--ui`).
+    pub ui: bool,
+    /// Pass every task's output straight through (`--raw`).
+    pub raw: bool,
+}
+
 pub async fn watch_mode(
     cfg: anymon_config::Config,
     watch: Option<Vec<String>>,
     debounce_ms: u64,
     kill_timeout: u64,
+    opts: WatchOptions,
 ) -> Result<()> {
-    use globset::GlobSetBuilder;
-    use tokio::sync::{broadcast, Mutex};
+    use tokio::sync::broadcast;
+    let WatchOptions { keys, ui, raw } = opts;
 
     let mut debounce_ms = debounce_ms;
     if let Some(g) = &cfg.global {
@@ -145,13 +739,13 @@ pub async fn watch_mode(
         }
     }
 
-    let tasks = cfg.task.unwrap_or_default();
+    let tasks = cfg.task.as_deref().unwrap_or_default();
     if tasks.is_empty() {
         eprintln!("{} no tasks defined in config", pref());
         return Ok(());
     }
 
-    // Determine watch roots (either provided, or config file dir, or current dir)
+    // Determine watch roots (either provided, or the config file dir)
     let roots: Vec<PathBuf> = if let Some(w) = watch.clone() {
         w.into_iter()
             .map(PathBuf::from)
@@ -163,314 +757,1588 @@ pub async fn watch_mode(
                 }
             })
             .collect()
+    } else if let Some(dir) = &cfg.base_dir {
+        vec![dir.clone()]
     } else {
         vec![std::env::current_dir()?]
     };
 
     let mut specs = Vec::new();
+    let mut logs = logfile::LogFiles::default();
     for t in tasks.iter() {
-        let mut builder = GlobSetBuilder::new();
-        for pat in &t.watch {
-            if let Ok(g) = globset::Glob::new(pat) {
-                let _ = builder.add(g);
-            }
-            for root in roots.iter() {
-                let combined = root.join(pat).to_string_lossy().replace('\\', "/");
-                if let Ok(g2) = globset::Glob::new(&combined) {
-                    let _ = builder.add(g2);
+        let spawn = SpawnOptions::for_task(&cfg, t)?;
+        // A task with a working directory matches its globs against it.
+        let task_roots = match &spawn.cwd {
+            Some(dir) => vec![dir.clone()],
+            None => roots.clone(),
+        };
+
+        let (excluded, watched): (Vec<&String>, Vec<&String>) =
+            t.watch.iter().partition(|pat| pat.starts_with('!'));
+        let globset = build_globset(watched.into_iter().map(String::as_str), &task_roots)
+            .map_err(|e| anyhow::anyhow!("task '{}': invalid watch pattern {}", t.name, e))?;
+        let ignore = build_globset(
+            excluded
+                .into_iter()
+                .map(|pat| &pat[1..])
+                .chain(t.ignore.iter().flatten().map(String::as_str)),
+            &task_roots,
+        )
+        .map_err(|e| anyhow::anyhow!("task '{}': invalid ignore pattern {}", t.name, e))?;
+        let global = cfg.global.as_ref();
+        let output = if raw {
+            OutputMode::Inherit
+        } else {
+            t.output
+                .or(global.and_then(|g| g.output))
+                .unwrap_or_default()
+        };
+        let log = match t
+            .log_file
+            .as_ref()
+            .or(global.and_then(|g| g.log_file.as_ref()))
+        {
+            Some(template) => {
+                let path = cfg.resolve_path(Path::new(&template.replace("{name}", &t.name)));
+                let max_size = t
+                    .log_max_size
+                    .or(global.and_then(|g| g.log_max_size))
+                    .unwrap_or(logfile::DEFAULT_MAX_SIZE);
+                let keep = t
+                    .log_keep
+                    .or(global.and_then(|g| g.log_keep))
+                    .unwrap_or(logfile::DEFAULT_KEEP);
+                let log = logs.open(path.clone(), max_size, keep).map_err(|e| {
+                    anyhow::anyhow!(
+                        "task '{}': cannot open log file {}: {}",
+                        t.name,
+                        path.display(),
+                        e
+                    )
+                })?;
+                if output == OutputMode::Inherit && !ui {
+                    eprintln!(
+                        "{} log_file needs captured output, nothing is logged with output = \"inherit\"",
+                        pref_task(&t.name)
+                    );
                 }
+                Some(log)
             }
-        }
-
-        let globset = builder
-            .build()
-            .unwrap_or_else(|_| globset::GlobSet::empty());
+            None => None,
+        };
         specs.push(TaskSpec {
             name: t.name.clone(),
-            run: t.run.clone(),
+            run: t.run.clone().into(),
             restart: t.restart.unwrap_or(true),
+            per_file: t.per_file.unwrap_or(false),
+            on_busy: t.on_busy.unwrap_or_default(),
+            depends_on: t.depends_on.clone().unwrap_or_default(),
+            hooks: Hooks::from_task(t),
+            restart_on_exit: t.restart_on_exit.unwrap_or_default(),
+            restart_delay: std::time::Duration::from_millis(t.restart_delay.unwrap_or(500)),
+            max_retries: t.max_retries.unwrap_or(5),
             globset,
-            roots: roots.clone(),
+            ignore,
+            roots: task_roots,
+            stop_signal: t.stop_signal.unwrap_or_default(),
+            output,
+            log,
+            spawn,
         });
     }
 
-    // Build ignore globset from global.ignore (resolve against roots too)
-    use globset::GlobSetBuilder as GSBuilder;
-    let mut ignore_builder = GSBuilder::new();
-    if let Some(g) = &cfg.global {
-        if let Some(ignore_patterns) = &g.ignore {
-            for pat in ignore_patterns.iter() {
-                if let Ok(gp) = globset::Glob::new(pat) {
-                    let _ = ignore_builder.add(gp);
-                }
-                for root in roots.iter() {
-                    let combined = root.join(pat).to_string_lossy().replace('\\', "/");
-                    if let Ok(gp2) = globset::Glob::new(&combined) {
-                        let _ = ignore_builder.add(gp2);
-                    }
+    // Also watch task directories outside the default roots, unless the
+    // roots were given explicitly with --watch.
+    let mut watch_roots = roots.clone();
+    if watch.is_none() {
+        for spec in specs.iter() {
+            for root in spec.roots.iter() {
+                if !watch_roots.iter().any(|w| root.starts_with(w)) {
+                    watch_roots.push(root.clone());
                 }
             }
         }
     }
-    let ignore_globset = ignore_builder
-        .build()
-        .unwrap_or_else(|_| globset::GlobSet::empty());
+
+    // Build ignore globset from global.ignore (resolve against roots too)
+    let ignore_globset = build_globset(
+        cfg.global
+            .iter()
+            .flat_map(|g| g.ignore.iter().flatten())
+            .map(String::as_str),
+        &watch_roots,
+    )
+    .map_err(|e| anyhow::anyhow!("global: invalid ignore pattern {}", e))?;
 
     // Broadcast channel for filesystem events
-    let (tx, _rx) = broadcast::channel::<PathBuf>(1024);
-    // Broadcast channel for control commands typed on stdin (e.g., rs, restart, quit, status)
-    let (ctrl_tx, _ctrl_rx) = tokio::sync::broadcast::channel::<String>(32);
-    // oneshot to signal watch_mode shutdown from stdin
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let (tx, _rx) = broadcast::channel::<FileEvent>(1024);
+    // Broadcast channel for task commands typed on stdin (e.g., rs, stop api)
+    let (ctrl_tx, _ctrl_rx) = tokio::sync::broadcast::channel::<TaskCommand>(32);
+    // Signals watch_mode shutdown from stdin or the dashboard
+    let (quit_tx, mut quit_rx) = tokio::sync::mpsc::channel::<()>(1);
+    // Tells every task loop to stop its process and return
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    // How each run ended, for tasks that depend on it
+    let (outcome_tx, _outcome_rx) = broadcast::channel::<TaskOutcome>(64);
+
+    let env_files: Vec<PathBuf> = specs
+        .iter()
+        .flat_map(|spec| spec.spawn.env_files().map(Path::to_path_buf))
+        .collect();
 
-    // wrap tx and ignore set in Arcs to move into closure
     let tx_arc = tx.clone();
-    let ignore_arc = Arc::new(ignore_globset);
+    let use_gitignore = cfg
+        .global
+        .as_ref()
+        .and_then(|g| g.use_gitignore)
+        .unwrap_or(true);
+    let mut filter = EventFilter {
+        ignore: ignore_globset,
+        ignore_files: use_gitignore.then(|| gitignore::IgnoreFiles::new(&watch_roots)),
+        env_files: env_files.clone(),
+        logs: specs.iter().filter_map(|spec| spec.log.clone()).collect(),
+    };
 
     let mut watcher: notify::RecommendedWatcher =
         notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
             match res {
                 Ok(event) => {
+                    // Reads are not changes; reacting to them would loop as
+                    // soon as a task reads a watched file (e.g. its .env).
+                    if matches!(event.kind, notify::EventKind::Access(_)) {
+                        return;
+                    }
+                    let kind = ChangeKind::from(&event.kind);
                     for path in event.paths {
-                        // skip ignored paths early
-                        if !ignore_arc.is_empty() && ignore_arc.is_match(&path) {
-                            continue;
+                        if filter.accepts(&path) {
+                            let _ = tx_arc.send(FileEvent { path, kind });
                         }
-                        let _ = tx_arc.send(path);
                     }
                 }
                 Err(e) => eprintln!("{} watch error: {e}", pref()),
             }
         })?;
 
-    for root in roots.iter() {
+    for root in watch_roots.iter() {
         watcher.watch(root, notify::RecursiveMode::Recursive)?;
         println!("{} watching: {}", pref(), root.display());
     }
+    // Env files outside the roots: watch their directory so that edits (and
+    // editors that replace the file) are noticed.
+    let mut env_dirs: Vec<&Path> = Vec::new();
+    for file in env_files.iter() {
+        if let Some(dir) = file.parent() {
+            if !watch_roots.iter().any(|w| dir.starts_with(w)) && !env_dirs.contains(&dir) {
+                env_dirs.push(dir);
+            }
+        }
+    }
+    for dir in env_dirs {
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        println!("{} watching: {}", pref(), dir.display());
+    }
+
+    // Listen for Ctrl-C before anything is started, so that it doesn't kill
+    // anymon while tasks run. A task that has the terminal gets the SIGINT
+    // instead and passes it on when it dies from it.
+    let mut ctrl_c = CtrlC::new()?;
 
     // Task state holders
     let mut handles = Vec::new();
 
-    for spec in specs.into_iter() {
-        let rx = tx.subscribe();
-        let spec = Arc::new(spec);
+    // Subscribe every task before any of them runs, so that no outcome is
+    // missed by a dependent task that isn't set up yet.
+    let channels: Vec<TaskChannels> = specs
+        .iter()
+        .map(|_| TaskChannels {
+            events: tx.subscribe(),
+            ctrl: ctrl_tx.subscribe(),
+            outcomes: outcome_tx.subscribe(),
+            outcome_tx: outcome_tx.clone(),
+            shutdown: shutdown_rx.clone(),
+        })
+        .collect();
 
-        // Attempt to start the configured task once at startup. If spawning fails,
-        // the task loop will still try to start it on subsequent file changes.
-        let initial_child = match try_spawn(&spec.run) {
-            Ok(child) => {
-                println!("{} starting: {}", pref_task(&spec.name), spec.run);
-                Some(child)
+    let states: Vec<(String, SharedState)> = specs
+        .iter()
+        .map(|spec| {
+            let state = TaskState {
+                log_file: spec.log.as_ref().map(|log| log.path().to_path_buf()),
+                ..TaskState::default()
+            };
+            (spec.name.clone(), Arc::new(Mutex::new(state)))
+        })
+        .collect();
+
+    // The dashboard captures task output, so it has to be up before the
+    // first task starts.
+    let dashboard = if ui {
+        let (output_tx, output_rx) = tokio::sync::mpsc::unbounded_channel();
+        match ui::Ui::start(
+            states.clone(),
+            output_rx,
+            output_tx.clone(),
+            tx.subscribe(),
+            ctrl_tx.clone(),
+            quit_tx.clone(),
+        ) {
+            Ok(dashboard) => {
+                for spec in specs.iter_mut() {
+                    spec.spawn.output = Some(spec.output_sink(&output_tx));
+                }
+                Some(dashboard)
             }
             Err(e) => {
-                eprintln!("{} failed to spawn: {}", pref_task(&spec.name), e);
+                eprintln!("{} cannot start the dashboard: {}", pref(), e);
                 None
             }
-        };
+        }
+    } else {
+        None
+    };
+    if dashboard.is_none() {
+        let (output_tx, output_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(print_output(output_rx));
+        for spec in specs.iter_mut() {
+            if spec.output == OutputMode::Prefix {
+                spec.spawn.output = Some(spec.output_sink(&output_tx));
+            }
+        }
+    }
 
+    // Tasks get the terminal while they run, like a shell's foreground job,
+    // unless anymon reads single keys from it or draws the dashboard on it.
+    #[cfg(unix)]
+    if !keys && dashboard.is_none() {
+        terminal::enable();
+    }
+
+    for ((spec, channels), (_, state)) in specs.into_iter().zip(channels).zip(states.iter()) {
+        let spec = Arc::new(spec);
+
+        println!(
+            "{} shell mode: {}",
+            pref_task(&spec.name),
+            spec.run.mode(spec.spawn.shell)
+        );
         let handle = tokio::spawn(run_task_loop(
             spec.clone(),
-            Mutex::new(initial_child),
-            rx,
-            ctrl_tx.subscribe(),
+            channels,
+            state.clone(),
             debounce_ms,
             kill_timeout,
         ));
         handles.push(handle);
     }
 
-    // Spawn a task to read stdin lines and broadcast control commands. Keep
-    // the JoinHandle so we can abort it during shutdown to avoid hanging on
+    // Single-key controls need a terminal; otherwise (and without --keys)
+    // commands are read line by line. The terminal is restored when the
+    // guard is dropped at the end of watch mode.
+    let raw_mode = if keys && dashboard.is_none() {
+        let raw = keys::RawMode::enable();
+        match &raw {
+            Some(_) => println!("{} {}", pref(), keys::HELP),
+            None => println!(
+                "{} {}, reading line commands instead of keys",
+                pref(),
+                keys::UNAVAILABLE
+            ),
+        }
+        raw
+    } else {
+        None
+    };
+
+    // Spawn a task to read stdin and broadcast control commands. Keep the
+    // JoinHandle so we can abort it during shutdown to avoid hanging on
     // blocking stdin reads (notably on Windows).
-    let stdin_handle = {
-        let ctrl_tx = ctrl_tx.clone();
-        let shutdown_tx = shutdown_tx;
-        tokio::spawn(async move {
-            use tokio::io::{AsyncBufReadExt, BufReader};
-            let stdin = BufReader::new(tokio::io::stdin());
-            let mut lines = stdin.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                let cmd = line.trim().to_lowercase();
-                if cmd.is_empty() {
-                    continue;
-                }
-                let _ = ctrl_tx.send(cmd.clone());
-                if cmd == "quit" || cmd == "q" || cmd == "exit" {
-                    let _ = shutdown_tx.send(());
-                    break;
-                }
-            }
-        })
+    // The dashboard reads keys itself.
+    let stdin_handle = if dashboard.is_some() {
+        None
+    } else if raw_mode.is_some() {
+        Some(tokio::spawn(read_keys(
+            states.clone(),
+            ctrl_tx.clone(),
+            quit_tx,
+        )))
+    } else {
+        Some(tokio::spawn(read_lines(
+            states.clone(),
+            ctrl_tx.clone(),
+            quit_tx,
+        )))
     };
 
     // Wait for Ctrl-C or stdin-triggered shutdown and then exit
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            println!("{} received Ctrl-C, shutting down", pref());
-        }
-        _ = &mut shutdown_rx => {
-            println!("{} shutdown requested from stdin", pref());
-        }
+    let interrupted = tokio::select! {
+        _ = ctrl_c.recv() => true,
+        _ = quit_rx.recv() => false,
+    };
+    // Give the terminal back before anything else is printed.
+    let from_dashboard = dashboard.is_some();
+    if let Some(dashboard) = dashboard {
+        dashboard.stop();
+    }
+    if interrupted {
+        println!("{} received Ctrl-C, shutting down", pref());
+    } else if from_dashboard {
+        println!("{} shutdown requested from the dashboard", pref());
+    } else {
+        println!("{} shutdown requested from stdin", pref());
     }
 
     // Abort stdin reader to ensure it doesn't keep the process alive on some
     // platforms (async stdin reads can block cancellation). Then wait for the
     // task to finish.
-    stdin_handle.abort();
-    let _ = stdin_handle.await;
+    if let Some(stdin_handle) = stdin_handle {
+        stdin_handle.abort();
+        let _ = stdin_handle.await;
+    }
 
-    // Note: child processes will be killed by individual task loops on drop if implemented
+    // Ask every task loop to stop its process (stop signal first, SIGKILL
+    // after the kill timeout) and wait for them to report back. A second
+    // Ctrl-C skips the wait.
+    let _ = shutdown_tx.send(true);
+    let wait_all = async {
+        for h in handles.iter_mut() {
+            let _ = h.await;
+        }
+    };
+    tokio::select! {
+        _ = wait_all => {}
+        _ = ctrl_c.recv() => {
+            println!("{} received second Ctrl-C, exiting without waiting", pref());
+        }
+    }
     for h in handles {
         h.abort();
     }
+    drop(raw_mode);
 
     Ok(())
 }
 
+/// Ctrl-C, listened for from the moment this is created.
+struct CtrlC {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    signal: tokio::signal::windows::CtrlC,
+}
+
+impl CtrlC {
+    fn new() -> std::io::Result<CtrlC> {
+        #[cfg(unix)]
+        let signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+        #[cfg(windows)]
+        let signal = tokio::signal::windows::ctrl_c()?;
+        Ok(CtrlC { signal })
+    }
+
+    async fn recv(&mut self) {
+        self.signal.recv().await;
+    }
+}
+
+/// Decides which paths reported by the watcher reach the tasks.
+struct EventFilter {
+    /// `global.ignore`.
+    ignore: GlobSet,
+    ignore_files: Option<gitignore::IgnoreFiles>,
+    /// Env files always trigger their tasks, even if they match an ignore
+    /// pattern (.env is commonly ignored).
+    env_files: Vec<PathBuf>,
+    /// Task log files never do: every line a task logs would trigger it
+    /// again.
+    logs: Vec<Arc<logfile::LogFile>>,
+}
+
+impl EventFilter {
+    fn accepts(&mut self, path: &Path) -> bool {
+        if self.logs.iter().any(|log| log.owns(path)) {
+            return false;
+        }
+        if self.env_files.iter().any(|file| file == path) {
+            return true;
+        }
+        let ignored = (!self.ignore.is_empty() && self.ignore.is_match(path))
+            || self
+                .ignore_files
+                .as_mut()
+                .is_some_and(|files| files.is_ignored(path));
+        !ignored
+    }
+}
+
+/// Build a glob set from `patterns`, each added both as given and joined to
+/// every root.
+fn build_globset<'a>(
+    patterns: impl Iterator<Item = &'a str>,
+    roots: &[PathBuf],
+) -> Result<GlobSet, String> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pat in patterns {
+        builder.add(anymon_config::parse_glob(pat)?);
+        for root in roots.iter() {
+            // A root containing glob syntax can't be combined with the
+            // pattern; the relative form above still applies.
+            let combined = root.join(pat).to_string_lossy().replace('\\', "/");
+            if let Ok(g2) = globset::Glob::new(&combined) {
+                builder.add(g2);
+            }
+        }
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Whether a read from stdin failed because anymon is in the background,
+/// while a task has the terminal.
+fn terminal_taken(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    let taken = e.raw_os_error() == Some(libc::EIO);
+    #[cfg(not(unix))]
+    let taken = {
+        let _ = e;
+        false
+    };
+    taken
+}
+
+/// Print the state of `target`, or of every task.
+fn print_status(states: &[(String, SharedState)], target: Option<&str>) {
+    for (name, state) in states.iter() {
+        if target.is_some_and(|t| t != name) {
+            continue;
+        }
+        if let Ok(state) = state.lock() {
+            println!("{} status: {}", pref_task(name), state);
+        }
+    }
+}
+
+/// Read control commands from stdin, one per line.
+async fn read_lines(
+    states: Vec<(String, SharedState)>,
+    ctrl_tx: tokio::sync::broadcast::Sender<TaskCommand>,
+    quit_tx: tokio::sync::mpsc::Sender<()>,
+) {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    let stdin = BufReader::new(tokio::io::stdin());
+    let mut lines = stdin.lines();
+    let unknown = |target: &Option<String>| match target {
+        Some(name) if !states.iter().any(|(n, _)| n == name) => {
+            eprintln!("{} no task named '{}'", pref(), name);
+            true
+        }
+        _ => false,
+    };
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            // A task has the terminal; read again once it is back.
+            Err(e) if terminal_taken(&e) => {
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                continue;
+            }
+            _ => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Control::parse(&line) {
+            Ok(Control::Quit) => {
+                let _ = quit_tx.try_send(());
+                break;
+            }
+            Ok(Control::Help) => println!("{}", control::HELP),
+            Ok(Control::List) => {
+                for (i, (name, _)) in states.iter().enumerate() {
+                    println!("{} {}. {}", pref(), i + 1, name);
+                }
+            }
+            Ok(Control::Status(target)) => {
+                if !unknown(&target) {
+                    print_status(&states, target.as_deref());
+                }
+            }
+            Ok(Control::Task(cmd)) => {
+                if !unknown(&cmd.target) {
+                    let _ = ctrl_tx.send(cmd);
+                }
+            }
+            Err(e) => eprintln!("{} {}", pref(), e),
+        }
+    }
+}
+
+/// Read single key presses from stdin (the terminal must be in cbreak mode).
+async fn read_keys(
+    states: Vec<(String, SharedState)>,
+    ctrl_tx: tokio::sync::broadcast::Sender<TaskCommand>,
+    quit_tx: tokio::sync::mpsc::Sender<()>,
+) {
+    use keys::Key;
+    use tokio::io::AsyncReadExt;
+    let mut stdin = tokio::io::stdin();
+    let mut buf = [0u8; 1];
+    let mut paused = false;
+    let send = |action, target| {
+        let _ = ctrl_tx.send(TaskCommand { action, target });
+    };
+    while let Ok(1) = stdin.read(&mut buf).await {
+        match Key::from_byte(buf[0]) {
+            Some(Key::RestartAll) => send(TaskAction::Restart, None),
+            Some(Key::Restart(i)) => match states.get(i) {
+                Some((name, _)) => send(TaskAction::Restart, Some(name.clone())),
+                None => eprintln!("{} no task #{}", pref(), i + 1),
+            },
+            Some(Key::Clear) => {
+                use std::io::Write;
+                print!("\x1b[2J\x1b[H");
+                let _ = std::io::stdout().flush();
+            }
+            Some(Key::TogglePause) => {
+                paused = !paused;
+                let action = if paused {
+                    TaskAction::Pause
+                } else {
+                    TaskAction::Resume
+                };
+                send(action, None);
+            }
+            Some(Key::Quit) => {
+                let _ = quit_tx.try_send(());
+                break;
+            }
+            None => {}
+        }
+    }
+}
+
+/// A run of a task: its `before` hooks, the main command and the hooks after
+/// it, one process at a time. Whichever is running can be stopped like the
+/// main command. A `per_file` run goes through this once for each file.
+pub struct Running {
+    /// The process running now: a hook or the main command.
+    pub child: tokio::process::Child,
+    pub trigger: Trigger,
+    /// When the run started, and again when its main command started.
+    pub started: std::time::Instant,
+    /// How the main command ended, once it has.
+    pub exited: Option<(std::process::ExitStatus, std::time::Instant)>,
+    /// Set once a step of the run fails: a `before` hook, a spawn or the main
+    /// command.
+    pub failed: bool,
+    stage: Stage,
+    /// What the current process runs for: `trigger`, or for `per_file` runs
+    /// the file being handled.
+    step: Trigger,
+    /// `per_file` runs still to go, one per file.
+    files: std::collections::VecDeque<Trigger>,
+}
+
+enum Stage {
+    /// The `before` hook at this index is running.
+    Before(usize),
+    Main,
+    /// A hook of kind `kind` is running after the main command; `rest` are
+    /// the hooks still to run.
+    After {
+        kind: &'static str,
+        rest: std::collections::VecDeque<(&'static str, Command)>,
+    },
+}
+
+impl Running {
+    /// Start the run with its first `before` hook, or the main command if
+    /// there are none. `per_file` tasks start with the first file.
+    fn start(spec: &TaskSpec, state: &SharedState, trigger: &Trigger) -> Option<Running> {
+        let mut files = match trigger {
+            Trigger::Changes(batch) if spec.per_file => (batch.paths.iter())
+                .map(|path| Trigger::Changes(batch.only(path)))
+                .collect(),
+            _ => std::collections::VecDeque::from([trigger.clone()]),
+        };
+        let mut failed = false;
+        let (child, stage, step) = spawn_next_file(spec, state, &mut files, &mut failed)?;
+        Some(Running {
+            child,
+            trigger: trigger.clone(),
+            started: std::time::Instant::now(),
+            exited: None,
+            failed,
+            stage,
+            step,
+            files,
+        })
+    }
+
+    /// Start the run for the next file that is left, if any.
+    fn next_file(&mut self, spec: &TaskSpec, state: &SharedState) -> bool {
+        match spawn_next_file(spec, state, &mut self.files, &mut self.failed) {
+            Some((child, stage, step)) => {
+                self.child = child;
+                self.stage = stage;
+                self.step = step;
+                self.started = std::time::Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move on after the current process exited with `status`: start the
+    /// next hook, the main command or the next file's run. Returns false once
+    /// the run is over.
+    fn advance(
+        &mut self,
+        spec: &TaskSpec,
+        state: &SharedState,
+        status: std::process::ExitStatus,
+    ) -> bool {
+        self.advance_step(spec, state, status) || self.next_file(spec, state)
+    }
+
+    /// Like `advance`, but within the run for the current file.
+    fn advance_step(
+        &mut self,
+        spec: &TaskSpec,
+        state: &SharedState,
+        status: std::process::ExitStatus,
+    ) -> bool {
+        let name = &spec.name;
+        match &mut self.stage {
+            Stage::Before(i) => {
+                let next = *i + 1;
+                if !status.success() {
+                    eprintln!("{} before hook failed: {}", pref_task(name), status);
+                    eprintln!("{} not starting", pref_task(name));
+                    self.failed = true;
+                    return false;
+                }
+                let child = match spec.hooks.before.get(next) {
+                    Some(hook) => spawn_hook(spec, "before", hook, &self.step, None),
+                    None => {
+                        let child = spawn_main(spec, state, &self.step);
+                        if child.is_some() {
+                            self.started = std::time::Instant::now();
+                            self.stage = Stage::Main;
+                        }
+                        child
+                    }
+                };
+                let Some(child) = child else {
+                    if matches!(self.stage, Stage::Before(_)) {
+                        eprintln!("{} not starting", pref_task(name));
+                    }
+                    self.failed = true;
+                    return false;
+                };
+                self.child = child;
+                if let Stage::Before(i) = &mut self.stage {
+                    *i = next;
+                }
+                true
+            }
+            Stage::Main => {
+                if !spec.restart {
+                    println!("{} finished: {}", pref_task(name), status);
+                } else if status.success() {
+                    println!("{} exited: {}", pref_task(name), status);
+                } else {
+                    eprintln!("{} crashed: {}", pref_task(name), exit_reason(status));
+                }
+                self.exited = Some((status, std::time::Instant::now()));
+                self.failed |= !status.success();
+                let hooks = &spec.hooks;
+                let (kind, outcome) = if status.success() {
+                    ("on_success", &hooks.on_success)
+                } else {
+                    ("on_failure", &hooks.on_failure)
+                };
+                let rest = (hooks.after.iter().map(|hook| ("after", hook)))
+                    .chain(outcome.iter().map(|hook| (kind, hook)))
+                    .map(|(kind, hook)| (kind, hook.clone()))
+                    .collect();
+                self.stage = Stage::After {
+                    kind: "after",
+                    rest,
+                };
+                self.next_hook(spec)
+            }
+            Stage::After { kind, rest } => {
+                if !status.success() {
+                    eprintln!("{} {} hook failed: {}", pref_task(name), kind, status);
+                    // A failing hook skips the rest of its list.
+                    let failed = *kind;
+                    rest.retain(|(kind, _)| *kind != failed);
+                }
+                self.next_hook(spec)
+            }
+        }
+    }
+
+    /// Start the next hook after the main command, skipping the rest of a
+    /// list whose hook can't be spawned. Returns false if none is left.
+    fn next_hook(&mut self, spec: &TaskSpec) -> bool {
+        let Stage::After { kind, rest } = &mut self.stage else {
+            return false;
+        };
+        let exit = self.exited.map(|(status, _)| status);
+        while let Some((next, hook)) = rest.pop_front() {
+            if let Some(child) = spawn_hook(spec, next, &hook, &self.step, exit) {
+                self.child = child;
+                *kind = next;
+                return true;
+            }
+            rest.retain(|(kind, _)| *kind != next);
+        }
+        false
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        // Only reached with a live process when the task loop is cancelled
+        // (a second Ctrl-C); don't leave it running behind anymon.
+        if let Some(pid) = self.child.id() {
+            #[cfg(unix)]
+            signal_group(pid, libc::SIGKILL);
+            #[cfg(not(unix))]
+            let _ = (pid, self.child.start_kill());
+        }
+    }
+}
+
+/// Spawn the first step of the run for the next of `files`: its first
+/// `before` hook or the main command. Files that fail to start are skipped
+/// and mark the run `failed`.
+fn spawn_next_file(
+    spec: &TaskSpec,
+    state: &SharedState,
+    files: &mut std::collections::VecDeque<Trigger>,
+    failed: &mut bool,
+) -> Option<(tokio::process::Child, Stage, Trigger)> {
+    while let Some(step) = files.pop_front() {
+        let started = match spec.hooks.before.first() {
+            Some(hook) => match spawn_hook(spec, "before", hook, &step, None) {
+                Some(child) => Some((child, Stage::Before(0))),
+                None => {
+                    eprintln!("{} not starting", pref_task(&spec.name));
+                    None
+                }
+            },
+            None => spawn_main(spec, state, &step).map(|child| (child, Stage::Main)),
+        };
+        match started {
+            Some((child, stage)) => return Some((child, stage, step)),
+            None => *failed = true,
+        }
+    }
+    None
+}
+
+/// Spawn one of the task's hooks, reporting a failure to spawn.
+fn spawn_hook(
+    spec: &TaskSpec,
+    kind: &str,
+    hook: &Command,
+    trigger: &Trigger,
+    exit: Option<std::process::ExitStatus>,
+) -> Option<tokio::process::Child> {
+    println!(
+        "{} {}: {}",
+        pref_task(&spec.name),
+        kind,
+        hook.expand(trigger.paths(), spec.spawn.shell)
+    );
+    match spec.spawn_with(hook, trigger, exit) {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("{} {} hook failed: {}", pref_task(&spec.name), kind, e);
+            None
+        }
+    }
+}
+
+/// Spawn the task's main command, counting the run.
+fn spawn_main(
+    spec: &TaskSpec,
+    state: &SharedState,
+    trigger: &Trigger,
+) -> Option<tokio::process::Child> {
+    println!(
+        "{} starting: {}",
+        pref_task(&spec.name),
+        spec.run.expand(trigger.paths(), spec.spawn.shell)
+    );
+    if let Ok(mut state) = state.lock() {
+        state.runs += 1;
+        state.last_trigger = trigger.paths().first().cloned();
+    }
+    match spec.spawn(trigger) {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("{} failed to spawn: {}", pref_task(&spec.name), e);
+            None
+        }
+    }
+}
+
+/// Start the task for `trigger`, reporting what happens. Tasks whose command
+/// needs changed files to fill in placeholders (or that run `per_file`) are
+/// skipped when there are none.
+fn start_task(spec: &TaskSpec, state: &SharedState, trigger: &Trigger) -> Option<Running> {
+    if (spec.per_file || spec.run.has_placeholders()) && trigger.paths().is_empty() {
+        println!("{} waiting for changed files", pref_task(&spec.name));
+        return None;
+    }
+
+    Running::start(spec, state, trigger)
+}
+
+/// Wait for the task's process to exit. Only polled while there is one.
+async fn wait_child(running: &mut Option<Running>) -> std::io::Result<std::process::ExitStatus> {
+    match running {
+        Some(r) => {
+            let pid = r.child.id();
+            let status = r.child.wait().await;
+            #[cfg(unix)]
+            if let Some(pid) = pid {
+                terminal::reclaim(pid, status.as_ref().ok().copied());
+            }
+            #[cfg(not(unix))]
+            let _ = pid;
+            status
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Stop the task's process, if it is running.
+async fn stop_running(spec: &TaskSpec, running: &mut Option<Running>, kill_timeout: u64) {
+    if let Some(mut r) = running.take() {
+        println!("{} stopping existing process...", pref_task(&spec.name));
+        if let Some(status) =
+            stop_child(&spec.name, &mut r.child, spec.stop_signal, kill_timeout).await
+        {
+            println!("{} stopped: {}", pref_task(&spec.name), status);
+        }
+    }
+}
+
+/// (Re)start the task for `trigger`. A run-to-completion task that is still
+/// busy follows its `on_busy` setting; a queued rerun collects the changes of
+/// every trigger that arrives in the meantime. Nothing new is started once
+/// `shutdown` is set, which can happen while the old process is stopping.
+async fn trigger_task(
+    spec: &TaskSpec,
+    state: &SharedState,
+    running: &mut Option<Running>,
+    queued: &mut Option<Trigger>,
+    trigger: Trigger,
+    kill_timeout: u64,
+    shutdown: &tokio::sync::watch::Receiver<bool>,
+) {
+    // `per_file` runs go through every file, so they are never cut short.
+    if (spec.restart && !spec.per_file) || running.is_none() {
+        stop_running(spec, running, kill_timeout).await;
+        if !*shutdown.borrow() {
+            *running = start_task(spec, state, &trigger);
+        }
+        return;
+    }
+    match spec.on_busy {
+        OnBusy::Queue => {
+            println!("{} busy, queued a rerun", pref_task(&spec.name));
+            match (queued.as_mut(), trigger) {
+                (Some(Trigger::Changes(q)), Trigger::Changes(batch)) => q.merge(batch),
+                (Some(q), trigger @ Trigger::Changes(_)) => *q = trigger,
+                (Some(_), _) => {}
+                (None, trigger) => *queued = Some(trigger),
+            }
+        }
+        OnBusy::Restart => {
+            stop_running(spec, running, kill_timeout).await;
+            if !*shutdown.borrow() {
+                *running = start_task(spec, state, &trigger);
+            }
+        }
+        OnBusy::Ignore => {
+            println!("{} busy, ignoring changes", pref_task(&spec.name));
+        }
+    }
+}
+
+/// A run that lasted this long counts as healthy and resets the automatic
+/// restart count.
+const STABLE_RUN: std::time::Duration = std::time::Duration::from_secs(10);
+/// Upper bound for the automatic restart backoff.
+const MAX_RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Channels connecting a task loop to the watcher, the control input, the
+/// other tasks and shutdown.
+pub struct TaskChannels {
+    pub events: tokio::sync::broadcast::Receiver<FileEvent>,
+    pub ctrl: tokio::sync::broadcast::Receiver<TaskCommand>,
+    /// Outcomes of every task's runs (including this one's).
+    pub outcomes: tokio::sync::broadcast::Receiver<TaskOutcome>,
+    pub outcome_tx: tokio::sync::broadcast::Sender<TaskOutcome>,
+    pub shutdown: tokio::sync::watch::Receiver<bool>,
+}
+
 pub async fn run_task_loop(
     spec: Arc<TaskSpec>,
-    child_slot: tokio::sync::Mutex<Option<tokio::process::Child>>,
-    mut rx: tokio::sync::broadcast::Receiver<PathBuf>,
-    mut ctrl_rx: tokio::sync::broadcast::Receiver<String>,
+    mut channels: TaskChannels,
+    state: SharedState,
     debounce_ms: u64,
     kill_timeout: u64,
 ) {
-    use std::time::{Duration, Instant};
+    use tokio::sync::broadcast::error::RecvError;
+    use tokio::time::{Duration, Instant};
+
+    // Changes collected during the current debounce window.
+    let mut pending: Option<ChangeBatch> = None;
+    let mut deadline = Instant::now();
+    // A rerun waiting for the current run to finish (`on_busy = "queue"`).
+    let mut queued: Option<Trigger> = None;
+    // Dependencies that haven't succeeded yet; the task starts once this is
+    // empty.
+    let mut waiting: Vec<String> = spec.depends_on.clone();
+    // Automatic restarts in a row, and the next one if it is scheduled.
+    let mut retries: u32 = 0;
+    let mut retry: Option<(Instant, Trigger)> = None;
+    // How the last run ended, and when.
+    let mut last_exit: Option<(std::process::ExitStatus, std::time::Instant)> = None;
+    // Set by `pause` (file changes are ignored) and `stop` (every trigger is).
+    let mut paused = false;
+    let mut stopped = false;
+
+    // Start the task once at startup. If spawning fails, it is tried again on
+    // the next change. Tasks with dependencies start once all of them have
+    // succeeded.
+    let mut running = if waiting.is_empty() {
+        start_task(&spec, &state, &Trigger::Initial)
+    } else {
+        println!(
+            "{} waiting for: {}",
+            pref_task(&spec.name),
+            waiting.join(", ")
+        );
+        None
+    };
 
-    let mut last_event: Instant;
     loop {
+        // Publish what the task is doing before waiting for the next event.
+        let phase = if let Some(run) = &running {
+            Phase::Running {
+                pid: run.child.id(),
+                since: run.started,
+            }
+        } else if stopped {
+            Phase::Idle
+        } else if let (Some((next, _)), Some((status, _))) = (&retry, last_exit) {
+            Phase::CrashLooping {
+                status,
+                retries,
+                next: next.into_std(),
+            }
+        } else if !waiting.is_empty() {
+            Phase::Waiting(waiting.clone())
+        } else if let Some((status, at)) = last_exit {
+            Phase::Exited { status, at }
+        } else {
+            Phase::Idle
+        };
+        if let Ok(mut state) = state.lock() {
+            state.phase = phase;
+            state.debouncing = pending.is_some();
+            state.paused = paused;
+            state.last_exit = last_exit.map(|(status, _)| status);
+        }
+
         tokio::select! {
-            recv = rx.recv() => match recv {
-                Ok(path) => {
-                    // check globset against path relative to any of the spec roots
-                    let mut matched = false;
-                    if spec.globset.is_empty() {
-                        matched = true;
-                    } else {
-                        for root in spec.roots.iter() {
-                            if let Ok(rel) = path.strip_prefix(root) {
-                                if spec.globset.is_match(rel) {
-                                    matched = true;
-                                    break;
-                                }
-                            }
+            recv = channels.events.recv() => match recv {
+                Ok(event) => {
+                    if paused || stopped || !spec.matches(&event.path) {
+                        continue;
+                    }
+                    // Every matching event extends the quiet window.
+                    deadline = Instant::now() + Duration::from_millis(debounce_ms);
+                    match pending.as_mut() {
+                        Some(batch) => batch.push(&event),
+                        None => {
+                            println!("{} change detected: {}", pref_task(&spec.name), event.path.display());
+                            let mut batch = ChangeBatch::default();
+                            batch.push(&event);
+                            pending = Some(batch);
                         }
                     }
-                    if !matched {
+                }
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("{} event lagged by {} messages", pref_task(&spec.name), n);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = tokio::time::sleep_until(deadline), if pending.is_some() => {
+                let batch = pending.take().unwrap_or_default();
+                if !waiting.is_empty() {
+                    println!("{} still waiting for: {}", pref_task(&spec.name), waiting.join(", "));
+                    continue;
+                }
+                retry = None;
+                retries = 0;
+                trigger_task(&spec, &state, &mut running, &mut queued, Trigger::Changes(batch), kill_timeout, &channels.shutdown).await;
+            },
+            status = wait_child(&mut running) => {
+                let Some(run) = running.as_mut() else {
+                    continue;
+                };
+                let status = match status {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{} wait failed: {}", pref_task(&spec.name), e);
+                        running = None;
                         continue;
                     }
-                    println!("{} change detected: {}", pref_task(&spec.name), path.display());
-
-                    // Wait for a quiet window (debounce). Listen for new events while
-                    // sleeping so we can update `last_event` and extend the window.
-                    last_event = Instant::now();
-                    loop {
-                        let sleep = tokio::time::sleep(Duration::from_millis(debounce_ms));
-                        tokio::pin!(sleep);
-                        tokio::select! {
-                            _ = &mut sleep => {
-                                if last_event.elapsed() >= Duration::from_millis(debounce_ms) {
-                                    break;
-                                } else {
-                                    continue;
-                                }
-                            }
-                            recv = rx.recv() => match recv {
-                                Ok(next_path) => {
-                                    // update last_event only for matching paths
-                                    let mut matched_next = false;
-                                    if spec.globset.is_empty() {
-                                        matched_next = true;
-                                    } else {
-                                        for root in spec.roots.iter() {
-                                            if let Ok(rel) = next_path.strip_prefix(root) {
-                                                if spec.globset.is_match(rel) {
-                                                    matched_next = true;
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    if matched_next {
-                                        last_event = Instant::now();
-                                    }
-                                    continue;
-                                }
-                                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                                    eprintln!("{} event lagged by {} messages", pref_task(&spec.name), n);
-                                    continue;
-                                }
-                                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-                            }
-                        }
+                };
+                // Hooks run one after the other as part of the run.
+                if run.advance(&spec, &state, status) {
+                    if run.exited.is_some() {
+                        last_exit = run.exited;
                     }
-
-                    // (re)start process
-                    if spec.restart {
-                        // kill existing if present
-                        let mut guard = child_slot.lock().await;
-                        if let Some(mut c) = guard.take() {
-                            println!("{} stopping existing process...", pref_task(&spec.name));
-                            let _ = c.kill().await;
-                            // wait for graceful exit with timeout
-                            let wait_fut = c.wait();
-                            match tokio::time::timeout(Duration::from_millis(kill_timeout), wait_fut).await {
-                                Ok(_) => println!("{} stopped", pref_task(&spec.name)),
-                                Err(_) => println!("{} kill timeout exceeded", pref_task(&spec.name)),
-                            }
-                        }
-
-                        println!("{} starting: {}", pref_task(&spec.name), spec.run);
-                        match try_spawn(&spec.run) {
-                            Ok(child) => {
-                                *guard = Some(child);
-                            }
-                            Err(e) => eprintln!("{} failed to spawn: {}", pref_task(&spec.name), e),
+                    continue;
+                }
+                let Some(run) = running.take() else {
+                    continue;
+                };
+                // A run that never got to its main command has no outcome.
+                // `per_file` runs report once, after the last file.
+                if run.exited.is_some() {
+                    last_exit = run.exited;
+                    let _ = channels.outcome_tx.send(TaskOutcome {
+                        task: spec.name.clone(),
+                        success: !run.failed,
+                    });
+                }
+                if *channels.shutdown.borrow() {
+                    continue;
+                }
+                if let Some(trigger) = queued.take() {
+                    retries = 0;
+                    running = start_task(&spec, &state, &trigger);
+                    continue;
+                }
+                if run.exited.is_none() {
+                    continue;
+                }
+                let again = match spec.restart_on_exit {
+                    RestartOnExit::Never => false,
+                    RestartOnExit::OnFailure => run.failed,
+                    RestartOnExit::Always => true,
+                };
+                if !again {
+                    continue;
+                }
+                if run.started.elapsed() >= STABLE_RUN {
+                    retries = 0;
+                }
+                if retries >= spec.max_retries {
+                    eprintln!(
+                        "{} exited {} times in a row, not restarting until the next change",
+                        pref_task(&spec.name),
+                        retries + 1
+                    );
+                    continue;
+                }
+                let delay = spec
+                    .restart_delay
+                    .saturating_mul(2u32.saturating_pow(retries))
+                    .min(MAX_RESTART_DELAY);
+                retries += 1;
+                println!(
+                    "{} restarting in {}ms (attempt {}/{})",
+                    pref_task(&spec.name),
+                    delay.as_millis(),
+                    retries,
+                    spec.max_retries
+                );
+                retry = Some((Instant::now() + delay, run.trigger.clone()));
+            },
+            _ = tokio::time::sleep_until(retry.as_ref().map_or(deadline, |r| r.0)), if retry.is_some() => {
+                if let Some((_, trigger)) = retry.take() {
+                    if !*channels.shutdown.borrow() {
+                        running = start_task(&spec, &state, &trigger);
+                    }
+                }
+            },
+            outcome = channels.outcomes.recv() => match outcome {
+                Ok(outcome) if !stopped && spec.depends_on.contains(&outcome.task) => {
+                    if !outcome.success {
+                        println!("{} '{}' failed, not restarting", pref_task(&spec.name), outcome.task);
+                        continue;
+                    }
+                    if !waiting.is_empty() {
+                        waiting.retain(|dep| dep != &outcome.task);
+                        if !waiting.is_empty() {
+                            continue;
                         }
                     }
+                    retry = None;
+                    retries = 0;
+                    let trigger = Trigger::Dependency(outcome.task);
+                    trigger_task(&spec, &state, &mut running, &mut queued, trigger, kill_timeout, &channels.shutdown).await;
                 }
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                    eprintln!("{} event lagged by {} messages", pref_task(&spec.name), n);
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("{} outcome channel lagged by {}", pref_task(&spec.name), n);
                 }
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                Err(RecvError::Closed) => break,
             },
-            ctrl = ctrl_rx.recv() => match ctrl {
-                Ok(cmd) => {
-                    match cmd.as_str() {
-                        "rs" | "restart" => {
-                            let mut guard = child_slot.lock().await;
-                            if let Some(mut c) = guard.take() {
-                                println!("{} restarting (stop)...", pref_task(&spec.name));
-                                let _ = c.kill().await;
-                            }
-                            match try_spawn(&spec.run) {
-                                Ok(child) => {
-                                    *guard = Some(child);
-                                    println!("{} restarted", pref_task(&spec.name));
-                                }
-                                Err(e) => eprintln!("{} restart failed: {}", pref_task(&spec.name), e),
-                            }
+            ctrl = channels.ctrl.recv() => match ctrl {
+                Ok(cmd) if cmd.applies_to(&spec.name) => match cmd.action {
+                    TaskAction::Restart | TaskAction::Start => {
+                        if cmd.action == TaskAction::Start && running.is_some() {
+                            println!("{} already running", pref_task(&spec.name));
+                            continue;
                         }
-                        "status" => {
-                            let guard = child_slot.lock().await;
-                            if guard.is_some() {
-                                println!("{} status: running", pref_task(&spec.name));
-                            } else {
-                                println!("{} status: stopped", pref_task(&spec.name));
-                            }
+                        if let Some(mut r) = running.take() {
+                            println!("{} restarting (stop)...", pref_task(&spec.name));
+                            stop_child(&spec.name, &mut r.child, spec.stop_signal, kill_timeout).await;
                         }
-                        "quit" | "q" | "exit" => {
-                            println!("{} quitting task loop", pref_task(&spec.name));
-                            break;
+                        stopped = false;
+                        queued = None;
+                        waiting.clear();
+                        retry = None;
+                        retries = 0;
+                        if !*channels.shutdown.borrow() {
+                            running = start_task(&spec, &state, &Trigger::Manual);
                         }
-                        _ => {}
                     }
-                }
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    TaskAction::Stop => {
+                        stop_running(&spec, &mut running, kill_timeout).await;
+                        stopped = true;
+                        pending = None;
+                        queued = None;
+                        retry = None;
+                        println!("{} stopped; 'start {}' to run it again", pref_task(&spec.name), spec.name);
+                    }
+                    TaskAction::Pause => {
+                        paused = true;
+                        pending = None;
+                        println!("{} paused, ignoring file changes", pref_task(&spec.name));
+                    }
+                    TaskAction::Resume => {
+                        paused = false;
+                        println!("{} resumed", pref_task(&spec.name));
+                    }
+                },
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => {
                     eprintln!("{} ctrl channel lagged by {}", pref_task(&spec.name), n);
                 }
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                Err(RecvError::Closed) => break,
+            },
+            _ = channels.shutdown.changed() => break,
+        }
+    }
+
+    // Whatever ended the loop, don't leave the process running behind us.
+    if let Some(mut r) = running.take() {
+        println!("{} stopping...", pref_task(&spec.name));
+        match stop_child(&spec.name, &mut r.child, spec.stop_signal, kill_timeout).await {
+            Some(status) => println!("{} exited: {}", pref_task(&spec.name), status),
+            None => eprintln!("{} could not confirm exit", pref_task(&spec.name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_placeholders() {
+        let files = [PathBuf::from("src/a b.scss"), PathBuf::from("src/c.scss")];
+        let line = Command::Line("sass {file} {dir}/out.{ext} -- {changed}".into());
+        assert_eq!(
+            line.expand(&files, Shell::Sh),
+            Command::Line("sass 'src/a b.scss' src/out.scss -- 'src/a b.scss' src/c.scss".into())
+        );
+        let files = [PathBuf::from("it's.scss")];
+        let line = Command::Line("sass {file}".into());
+        assert_eq!(
+            line.expand(&files, Shell::Sh),
+            Command::Line(r"sass 'it'\''s.scss'".into())
+        );
+        assert_eq!(
+            line.expand(&files, Shell::Pwsh),
+            Command::Line("sass 'it''s.scss'".into())
+        );
+        let files = [PathBuf::from("src/a b.scss"), PathBuf::from("src/c.scss")];
+        let argv = Command::Argv(vec!["fmt".into(), "{changed}".into(), "--ext={ext}".into()]);
+        assert_eq!(
+            argv.expand(&files, Shell::Pwsh),
+            Command::Argv(vec![
+                "fmt".into(),
+                "src/a b.scss".into(),
+                "src/c.scss".into(),
+                "--ext=scss".into()
+            ])
+        );
+        assert!(!Command::Line("cargo test".into()).has_placeholders());
+    }
+
+    #[test]
+    fn matches_watch_and_ignore_globs() {
+        let roots = vec![PathBuf::from("/p")];
+        let spec = TaskSpec {
+            name: "web".into(),
+            run: Command::Line("true".into()),
+            restart: true,
+            per_file: false,
+            on_busy: OnBusy::default(),
+            depends_on: Vec::new(),
+            hooks: Hooks::default(),
+            restart_on_exit: RestartOnExit::default(),
+            restart_delay: std::time::Duration::from_millis(500),
+            max_retries: 5,
+            globset: build_globset(["src/**"].into_iter(), &roots).unwrap(),
+            ignore: build_globset(["src/**/*.test.ts", "**/gen/**"].into_iter(), &roots).unwrap(),
+            roots,
+            stop_signal: StopSignal::default(),
+            output: OutputMode::default(),
+            log: None,
+            spawn: SpawnOptions::default(),
+        };
+        assert!(spec.matches(Path::new("/p/src/app.ts")));
+        assert!(!spec.matches(Path::new("/p/src/ui/app.test.ts")));
+        assert!(!spec.matches(Path::new("/p/src/gen/api.ts")));
+        assert!(!spec.matches(Path::new("/p/README.md")));
+
+        // An empty watch list matches nothing.
+        let spec = TaskSpec {
+            globset: GlobSet::empty(),
+            ..spec
+        };
+        assert!(!spec.matches(Path::new("/p/src/app.ts")));
+        assert!(build_globset(["src/{a,b"].into_iter(), &[]).is_err());
+    }
+
+    /// A task that watches everything under `/p` and runs `run` to
+    /// completion.
+    fn loop_spec(name: &str, run: Command) -> TaskSpec {
+        let roots = vec![PathBuf::from("/p")];
+        TaskSpec {
+            name: name.into(),
+            run,
+            restart: false,
+            per_file: false,
+            on_busy: OnBusy::default(),
+            depends_on: Vec::new(),
+            hooks: Hooks::default(),
+            restart_on_exit: RestartOnExit::default(),
+            restart_delay: std::time::Duration::from_millis(500),
+            max_retries: 5,
+            globset: build_globset(["**"].into_iter(), &roots).unwrap(),
+            ignore: GlobSet::empty(),
+            roots,
+            stop_signal: StopSignal::default(),
+            output: OutputMode::default(),
+            log: None,
+            spawn: SpawnOptions::default(),
+        }
+    }
+
+    /// A task loop fed by hand instead of by the watcher and stdin.
+    struct TestLoop {
+        events: tokio::sync::broadcast::Sender<FileEvent>,
+        /// Kept open: the loop ends when the control channel closes.
+        _ctrl: tokio::sync::broadcast::Sender<TaskCommand>,
+        outcomes: tokio::sync::broadcast::Receiver<TaskOutcome>,
+        shutdown: tokio::sync::watch::Sender<bool>,
+        state: SharedState,
+        handle: tokio::task::JoinHandle<()>,
+    }
+
+    impl TestLoop {
+        fn start(spec: TaskSpec, kill_timeout: u64) -> TestLoop {
+            let (events, events_rx) = tokio::sync::broadcast::channel(16);
+            let (ctrl, ctrl_rx) = tokio::sync::broadcast::channel(16);
+            let (outcome_tx, outcomes_rx) = tokio::sync::broadcast::channel(16);
+            let (shutdown, shutdown_rx) = tokio::sync::watch::channel(false);
+            let outcomes = outcome_tx.subscribe();
+            let channels = TaskChannels {
+                events: events_rx,
+                ctrl: ctrl_rx,
+                outcomes: outcomes_rx,
+                outcome_tx,
+                shutdown: shutdown_rx,
+            };
+            let state = SharedState::default();
+            let handle = tokio::spawn(run_task_loop(
+                Arc::new(spec),
+                channels,
+                state.clone(),
+                10,
+                kill_timeout,
+            ));
+            TestLoop {
+                events,
+                _ctrl: ctrl,
+                outcomes,
+                shutdown,
+                state,
+                handle,
+            }
+        }
+
+        fn change(&self, path: &str) {
+            let event = FileEvent {
+                path: PathBuf::from(path),
+                kind: ChangeKind::Modify,
+            };
+            self.events.send(event).unwrap();
+        }
+
+        /// The next outcome the task reports.
+        async fn outcome(&mut self) -> TaskOutcome {
+            let wait = std::time::Duration::from_secs(10);
+            let outcome = tokio::time::timeout(wait, self.outcomes.recv()).await;
+            outcome.expect("no outcome").unwrap()
+        }
+
+        fn runs(&self) -> u64 {
+            self.state.lock().unwrap().runs
+        }
+
+        async fn shut_down(&mut self) {
+            self.shutdown.send(true).unwrap();
+            (&mut self.handle).await.unwrap();
+        }
+    }
+
+    async fn sleep_ms(ms: u64) {
+        tokio::time::sleep(std::time::Duration::from_millis(ms)).await;
+    }
+
+    /// A fresh file for commands to append to, named after the test.
+    fn marker(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("anymon-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn marked(path: &Path) -> Vec<String> {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        text.lines().map(str::to_string).collect()
+    }
+
+    /// Start a run for `/p/a`, then send `/p/b` and `/p/c` as two more
+    /// batches while it is still going, and return what the runs logged.
+    #[cfg(unix)]
+    async fn busy_runs(test: &str, on_busy: OnBusy) -> Vec<String> {
+        let marker = marker(test);
+        let log = marker.display();
+        let run =
+            format!("echo start {{changed}} >> {log}; sleep 0.4; echo end {{changed}} >> {log}");
+        let spec = TaskSpec {
+            on_busy,
+            ..loop_spec(test, Command::Line(run))
+        };
+        let mut task = TestLoop::start(spec, 1000);
+        task.change("/p/a");
+        sleep_ms(150).await;
+        task.change("/p/b");
+        sleep_ms(50).await;
+        task.change("/p/c");
+        // Done once a run has ended and nothing more happened for longer than
+        // a run takes.
+        for _ in 0..20 {
+            let lines = marked(&marker);
+            sleep_ms(600).await;
+            if lines.last().is_some_and(|l| l.starts_with("end")) && marked(&marker) == lines {
+                break;
             }
         }
+        task.shut_down().await;
+        let lines = marked(&marker);
+        let _ = std::fs::remove_file(&marker);
+        lines
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn per_file_runs_each_file_in_turn() {
+        let marker = marker("per-file");
+        let script = format!("echo \"$1\" >> '{}'", marker.display());
+        let run = Command::Argv(vec![
+            "sh".into(),
+            "-c".into(),
+            script,
+            "sh".into(),
+            "{file}".into(),
+        ]);
+        let spec = TaskSpec {
+            per_file: true,
+            ..loop_spec("fmt", run)
+        };
+        let mut task = TestLoop::start(spec, 1000);
+        for file in ["/p/a.rs", "/p/b.rs", "/p/c.rs"] {
+            task.change(file);
+        }
+        // One outcome, after the last file.
+        let outcome = TaskOutcome {
+            task: "fmt".into(),
+            success: true,
+        };
+        assert_eq!(task.outcome().await, outcome);
+        assert_eq!(marked(&marker), ["/p/a.rs", "/p/b.rs", "/p/c.rs"]);
+        assert_eq!(task.runs(), 3);
+        task.shut_down().await;
+        assert!(task.outcomes.try_recv().is_err());
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shutdown_during_a_restart_starts_nothing() {
+        // Ignores the stop signal, so stopping it takes the kill timeout.
+        let run = Command::Line("trap '' TERM; sleep 30".into());
+        let spec = TaskSpec {
+            restart: true,
+            ..loop_spec("web", run)
+        };
+        let mut task = TestLoop::start(spec, 500);
+        sleep_ms(200).await;
+        assert_eq!(task.runs(), 1);
+        task.change("/p/a.rs");
+        sleep_ms(200).await;
+        task.shut_down().await;
+        assert_eq!(task.runs(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn queue_reruns_once_with_every_change() {
+        assert_eq!(
+            busy_runs("queue", OnBusy::Queue).await,
+            ["start /p/a", "end /p/a", "start /p/b /p/c", "end /p/b /p/c"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn ignore_drops_changes_while_busy() {
+        assert_eq!(
+            busy_runs("ignore", OnBusy::Ignore).await,
+            ["start /p/a", "end /p/a"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restart_stops_the_busy_run() {
+        assert_eq!(
+            busy_runs("restart", OnBusy::Restart).await,
+            ["start /p/a", "start /p/b", "start /p/c", "end /p/c"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hooks_run_in_order_around_the_command() {
+        let marker = marker("hooks");
+        let log = marker.display();
+        let hook = |text: &str| Command::Line(format!("echo {text} >> {log}"));
+        let spec = TaskSpec {
+            hooks: Hooks {
+                before: vec![hook("before 1"), hook("before 2")],
+                after: vec![hook("after $ANYMON_EXIT_CODE")],
+                on_success: vec![hook("success")],
+                on_failure: vec![hook("failure $ANYMON_EXIT_CODE")],
+            },
+            ..loop_spec("hooks", Command::Line(format!("echo run >> {log}; exit 3")))
+        };
+        let mut task = TestLoop::start(spec, 1000);
+        assert!(!task.outcome().await.success);
+        task.shut_down().await;
+        assert_eq!(
+            marked(&marker),
+            ["before 1", "before 2", "run", "after 3", "failure 3"]
+        );
+        std::fs::remove_file(&marker).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn failing_before_hook_skips_the_command() {
+        let marker = marker("before-fails");
+        let log = marker.display();
+        let spec = TaskSpec {
+            hooks: Hooks {
+                before: vec![Command::Line(format!("echo before >> {log}; exit 1"))],
+                after: vec![Command::Line(format!("echo after >> {log}"))],
+                ..Hooks::default()
+            },
+            ..loop_spec("hooks", Command::Line(format!("echo run >> {log}")))
+        };
+        let mut task = TestLoop::start(spec, 1000);
+        sleep_ms(300).await;
+        task.shut_down().await;
+        assert_eq!(task.runs(), 0);
+        // A run that never started has no outcome for dependents.
+        assert!(task.outcomes.try_recv().is_err());
+        assert_eq!(marked(&marker), ["before"]);
+        std::fs::remove_file(&marker).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn crashing_task_backs_off_and_gives_up() {
+        let spec = TaskSpec {
+            restart: true,
+            restart_on_exit: RestartOnExit::OnFailure,
+            restart_delay: std::time::Duration::from_millis(100),
+            max_retries: 2,
+            ..loop_spec("crash", Command::Argv(vec!["false".into()]))
+        };
+        let mut task = TestLoop::start(spec, 1000);
+        let first = task.outcome().await;
+        assert!(!first.success);
+        let started = std::time::Instant::now();
+        for _ in 0..2 {
+            assert_eq!(task.outcome().await, first);
+        }
+        // 100ms before the first retry, 200ms before the second.
+        assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+        sleep_ms(500).await;
+        assert_eq!(task.runs(), 3);
+        assert!(task.outcomes.try_recv().is_err());
+        match task.state.lock().unwrap().phase {
+            Phase::Exited { status, .. } => assert!(!status.success()),
+            ref phase => panic!("expected a failed exit, got {phase:?}"),
+        }
+        task.shut_down().await;
+    }
+
+    #[test]
+    fn skips_log_files_under_a_root() {
+        let root = std::env::temp_dir().join(format!("anymon-logroot-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let log = logfile::LogFile::open(root.join("logs/t.log"), 1024, 2).unwrap();
+        let mut filter = EventFilter {
+            ignore: GlobSet::empty(),
+            ignore_files: Some(gitignore::IgnoreFiles::new(std::slice::from_ref(&root))),
+            env_files: Vec::new(),
+            logs: vec![Arc::new(log)],
+        };
+        // `watch = ["**"]` covers the log, so it must never get through.
+        assert!(!filter.accepts(&root.join("logs/t.log")));
+        assert!(!filter.accepts(&root.join("logs/t.log.1")));
+        assert!(!filter.accepts(&root.join("logs/t.log.2")));
+        assert!(filter.accepts(&root.join("logs/other.log")));
+        assert!(filter.accepts(&root.join("src/main.rs")));
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
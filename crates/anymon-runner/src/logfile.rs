@@ -0,0 +1,179 @@
+//! Per-task log files (`log_file`), rotated by size.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::output::Stream;
+
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 5;
+
+/// An append-only log that moves `web.log` to `web.log.1` (and so on, up to
+/// `keep` files) once it would grow past `max_size` bytes.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    /// The open file and its current size.
+    file: Mutex<(File, u64)>,
+}
+
+impl LogFile {
+    /// Open (or create) the log at `path`, creating missing directories.
+    pub fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<LogFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(LogFile {
+            path,
+            max_size,
+            keep,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `path` is this log or one of its rotated files.
+    pub fn owns(&self, path: &Path) -> bool {
+        path == self.path || (1..=self.keep).any(|n| path == self.numbered(n))
+    }
+
+    /// The `n`th rotated file, e.g. `web.log.1`.
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    /// Append one line of `task`'s output with a timestamp. Write errors are
+    /// ignored so that a full disk doesn't take the task down.
+    pub fn write_line(&self, task: &str, stream: Stream, text: &str) {
+        let stream = match stream {
+            Stream::Stdout => "out",
+            Stream::Stderr => "err",
+            Stream::Anymon => "anymon",
+        };
+        let line = format!(
+            "{} {} {} {}\n",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            task,
+            stream,
+            text
+        );
+        let Ok(mut guard) = self.file.lock() else {
+            return;
+        };
+        let (file, size) = &mut *guard;
+        if *size > 0 && *size + line.len() as u64 > self.max_size {
+            if let Ok(new) = self.rotate() {
+                *file = new;
+                *size = 0;
+            }
+        }
+        if file.write_all(line.as_bytes()).is_ok() {
+            *size += line.len() as u64;
+        }
+    }
+
+    /// Shift the rotated files up by one, dropping the oldest, and start a
+    /// new log.
+    fn rotate(&self) -> io::Result<File> {
+        if self.keep == 0 {
+            return File::create(&self.path);
+        }
+        let _ = fs::remove_file(self.numbered(self.keep));
+        for n in (1..self.keep).rev() {
+            let from = self.numbered(n);
+            if from.exists() {
+                fs::rename(from, self.numbered(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.numbered(1))?;
+        append(&self.path)
+    }
+}
+
+/// The logs opened so far, so that tasks logging to the same path share one
+/// `LogFile` (and its size and rotation) instead of renaming the file under
+/// each other.
+#[derive(Debug, Default)]
+pub struct LogFiles(HashMap<PathBuf, Arc<LogFile>>);
+
+impl LogFiles {
+    /// The log at `path`, opened on first use. Size limits come from the
+    /// first task that opens it.
+    pub fn open(&mut self, path: PathBuf, max_size: u64, keep: usize) -> io::Result<Arc<LogFile>> {
+        if let Some(log) = self.0.get(&path) {
+            return Ok(log.clone());
+        }
+        let log = Arc::new(LogFile::open(path.clone(), max_size, keep)?);
+        self.0.insert(path, log.clone());
+        Ok(log)
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("anymon-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("logs/web.log");
+        // Each line is 24 (timestamp) + 4 ("web ") + 4 ("out ") + 5 ("line\n")
+        // bytes.
+        let log = LogFile::open(path.clone(), 80, 2).unwrap();
+        for _ in 0..7 {
+            log.write_line("web", Stream::Stdout, "line");
+        }
+        let lines = |p: &Path| fs::read_to_string(p).unwrap().lines().count();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&dir.join("logs/web.log.1")), 2);
+        assert_eq!(lines(&dir.join("logs/web.log.2")), 2);
+        assert!(!dir.join("logs/web.log.3").exists());
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .ends_with(" web out line\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tasks_share_a_log_path() {
+        let dir = std::env::temp_dir().join(format!("anymon-shared-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("all.log");
+        let mut logs = LogFiles::default();
+        let web = logs.open(path.clone(), 300, 2).unwrap();
+        let api = logs.open(path.clone(), 300, 2).unwrap();
+        assert!(Arc::ptr_eq(&web, &api));
+        for i in 0..20 {
+            web.write_line("web", Stream::Stdout, &format!("web line {i}"));
+            api.write_line("api", Stream::Stderr, &format!("api line {i}"));
+        }
+        for file in [path.clone(), dir.join("all.log.1"), dir.join("all.log.2")] {
+            assert!(fs::metadata(&file).unwrap().len() <= 300);
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text
+            .lines()
+            .last()
+            .unwrap()
+            .ends_with(" api err api line 19"));
+        assert!(text.contains(" web out web line 19\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
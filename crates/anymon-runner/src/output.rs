@@ -0,0 +1,154 @@
+//! Capturing the output of task processes instead of letting them inherit
+//! anymon's stdout and stderr.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::logfile::LogFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    /// A line anymon itself printed.
+    Anymon,
+}
+
+/// One line of output, without its line ending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    /// The task that produced the line, if known.
+    pub task: Option<String>,
+    pub stream: Stream,
+    pub text: String,
+}
+
+pub type OutputTx = tokio::sync::mpsc::UnboundedSender<OutputLine>;
+
+/// Where a task's captured output goes.
+#[derive(Debug, Clone)]
+pub struct OutputSink {
+    pub task: String,
+    pub tx: OutputTx,
+    /// Also written here, with timestamps.
+    pub log: Option<Arc<LogFile>>,
+}
+
+/// Forward the piped stdout and stderr of `child` to `sink`, line by line.
+pub fn capture(child: &mut tokio::process::Child, sink: &OutputSink) {
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward(stdout, sink.clone(), Stream::Stdout));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward(stderr, sink.clone(), Stream::Stderr));
+    }
+}
+
+/// How long a line without a newline (e.g. a prompt) waits for the rest
+/// before it is shown on its own.
+const PARTIAL_LINE_DELAY: Duration = Duration::from_millis(100);
+
+async fn forward<R: AsyncRead + Unpin>(mut reader: R, sink: OutputSink, stream: Stream) {
+    let mut lines = LineSplitter::default();
+    let mut buf = [0u8; 8192];
+    let send = |text: String| {
+        if let Some(log) = &sink.log {
+            log.write_line(&sink.task, stream, &text);
+        }
+        sink.tx
+            .send(OutputLine {
+                task: Some(sink.task.clone()),
+                stream,
+                text,
+            })
+            .is_ok()
+    };
+    loop {
+        let read = if lines.has_partial() {
+            match tokio::time::timeout(PARTIAL_LINE_DELAY, reader.read(&mut buf)).await {
+                Ok(read) => read,
+                Err(_) => {
+                    if lines.flush().is_some_and(|text| !send(text)) {
+                        break;
+                    }
+                    continue;
+                }
+            }
+        } else {
+            reader.read(&mut buf).await
+        };
+        match read {
+            Ok(0) | Err(_) => {
+                if let Some(text) = lines.flush() {
+                    send(text);
+                }
+                break;
+            }
+            Ok(n) => {
+                if !lines.push(&buf[..n]).into_iter().all(send) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Splits a byte stream into lines. `\r\n` ends a line like `\n`; a bare
+/// `\r` (progress bars redrawing themselves) keeps only the text after it.
+#[derive(Debug, Default)]
+struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Add `bytes` and return the lines they complete.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &b in bytes {
+            if b == b'\n' {
+                lines.push(Self::finish(std::mem::take(&mut self.partial)));
+            } else {
+                self.partial.push(b);
+            }
+        }
+        lines
+    }
+
+    fn has_partial(&self) -> bool {
+        !self.partial.is_empty()
+    }
+
+    /// Take the unfinished line, if any.
+    fn flush(&mut self) -> Option<String> {
+        self.has_partial()
+            .then(|| Self::finish(std::mem::take(&mut self.partial)))
+    }
+
+    fn finish(line: Vec<u8>) -> String {
+        let text = String::from_utf8_lossy(&line);
+        let text = text.strip_suffix('\r').unwrap_or(&text);
+        match text.rsplit_once('\r') {
+            Some((_, last)) => last.to_string(),
+            None => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines() {
+        let mut lines = LineSplitter::default();
+        assert_eq!(lines.push(b"one\r\ntw"), ["one"]);
+        assert!(lines.has_partial());
+        assert_eq!(lines.push(b"o\n\n"), ["two", ""]);
+        assert_eq!(lines.push(b" 10%\r 50%\r100%\n"), ["100%"]);
+        assert_eq!(lines.push(b"Password: "), Vec::<String>::new());
+        assert_eq!(lines.flush().as_deref(), Some("Password: "));
+        assert_eq!(lines.flush(), None);
+    }
+}
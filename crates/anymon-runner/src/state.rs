@@ -0,0 +1,143 @@
+//! What each task is doing right now, kept up to date by its task loop and
+//! read by the `status` command.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// State of one task, shared between its loop and status readers.
+pub type SharedState = Arc<Mutex<TaskState>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct TaskState {
+    pub phase: Phase,
+    /// Changes are being collected for the next run.
+    pub debouncing: bool,
+    /// File changes are ignored until `resume`.
+    pub paused: bool,
+    /// Number of times the task's command was started.
+    pub runs: u64,
+    /// First changed path of the trigger behind the latest run.
+    pub last_trigger: Option<PathBuf>,
+    /// How the latest run ended.
+    pub last_exit: Option<ExitStatus>,
+    /// Where the task's output is logged.
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum Phase {
+    /// Not started yet, or stopped.
+    #[default]
+    Idle,
+    /// Waiting for these dependencies to succeed for the first time.
+    Waiting(Vec<String>),
+    Running {
+        pid: Option<u32>,
+        since: Instant,
+    },
+    Exited {
+        status: ExitStatus,
+        at: Instant,
+    },
+    /// Exited on its own and is about to be restarted automatically.
+    CrashLooping {
+        status: ExitStatus,
+        retries: u32,
+        next: Instant,
+    },
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.phase {
+            Phase::Idle => write!(f, "stopped")?,
+            Phase::Waiting(deps) => write!(f, "waiting for {}", deps.join(", "))?,
+            Phase::Running { pid, since } => {
+                write!(f, "running (")?;
+                if let Some(pid) = pid {
+                    write!(f, "pid {}, ", pid)?;
+                }
+                write!(f, "up {})", format_duration(since.elapsed()))?
+            }
+            Phase::Exited { status, at } => write!(
+                f,
+                "exited ({}, {} ago)",
+                crate::exit_reason(*status),
+                format_duration(at.elapsed())
+            )?,
+            Phase::CrashLooping {
+                status,
+                retries,
+                next,
+            } => write!(
+                f,
+                "crash-looping ({}, restart #{} in {})",
+                crate::exit_reason(*status),
+                retries,
+                format_duration(next.saturating_duration_since(Instant::now()))
+            )?,
+        }
+        if self.paused {
+            write!(f, ", paused")?;
+        }
+        if self.debouncing {
+            write!(f, ", waiting for debounce")?;
+        }
+        write!(f, "; runs: {}", self.runs)?;
+        if let Some(path) = &self.last_trigger {
+            write!(f, "; last trigger: {}", path.display())?;
+        }
+        if let Some(path) = &self.log_file {
+            write!(f, "; log: {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Short human-readable duration: `850ms`, `12s`, `3m05s`, `1h02m`.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs == 0 {
+        format!("{}ms", d.as_millis())
+    } else if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+    }
+
+    #[test]
+    fn describes_state() {
+        let state = TaskState {
+            phase: Phase::Waiting(vec!["build".into()]),
+            debouncing: true,
+            paused: false,
+            runs: 2,
+            last_trigger: Some(PathBuf::from("/p/src/main.rs")),
+            last_exit: None,
+            log_file: Some(PathBuf::from("/p/logs/web.log")),
+        };
+        assert_eq!(
+            state.to_string(),
+            "waiting for build, waiting for debounce; runs: 2; last trigger: /p/src/main.rs; \
+             log: /p/logs/web.log"
+        );
+    }
+}
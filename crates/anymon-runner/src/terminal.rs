@@ -0,0 +1,86 @@
+//! Handing the terminal to tasks, the way a shell does for a foreground job.
+//!
+//! Every task runs in its own process group so that stopping it reaches
+//! everything it started. Once enabled, a task is also made the terminal's
+//! foreground group while it runs: it can read from and configure the
+//! terminal, and a Ctrl-C goes to it. anymon takes the terminal back when the
+//! task exits.
+
+use std::os::unix::process::ExitStatusExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Hand the terminal to tasks spawned from now on, if anymon has it. anymon
+/// then carries on from the background while a task has it: reading from it
+/// fails with EIO instead of stopping anymon (SIGTTIN), and taking it back
+/// doesn't stop it either (SIGTTOU). Task processes get the default handlers
+/// back in [`reset_signals`].
+pub fn enable() {
+    if !owns_terminal() {
+        return;
+    }
+    // SAFETY: setting a signal's disposition to SIG_IGN has no preconditions.
+    unsafe {
+        libc::signal(libc::SIGTTIN, libc::SIG_IGN);
+        libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether a task spawned now takes the terminal: handing it over is enabled
+/// and no other task has it.
+pub fn available() -> bool {
+    ENABLED.load(Ordering::Relaxed) && owns_terminal()
+}
+
+/// Whether anymon's process group has the terminal on stdin.
+fn owns_terminal() -> bool {
+    // SAFETY: plain queries on stdin and the own process.
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::tcgetpgrp(0) == libc::getpgrp() }
+}
+
+/// In a task's process between fork and exec: undo the signal dispositions
+/// set by [`enable`], which would otherwise be inherited.
+pub fn reset_signals() {
+    // SAFETY: async-signal-safe.
+    unsafe {
+        libc::signal(libc::SIGTTIN, libc::SIG_DFL);
+        libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+    }
+}
+
+/// In a task's process between fork and exec, once it leads its own process
+/// group: make that group the terminal's foreground group.
+pub fn take_terminal() {
+    // SAFETY: async-signal-safe. SIGTTOU is blocked for the call, as a
+    // background process asking for the terminal would be stopped by it.
+    unsafe {
+        let mut block: libc::sigset_t = std::mem::zeroed();
+        let mut old: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut block);
+        libc::sigaddset(&mut block, libc::SIGTTOU);
+        libc::sigprocmask(libc::SIG_BLOCK, &block, &mut old);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        libc::sigprocmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+    }
+}
+
+/// Take the terminal back after the task process `pid`, which led its own
+/// group, exited with `status`, if that group had the terminal. A task that
+/// was killed by Ctrl-C passes it on to anymon, so that one Ctrl-C still
+/// shuts everything down, including what the task left running in its group
+/// (background jobs of `sh` ignore SIGINT).
+pub fn reclaim(pid: u32, status: Option<std::process::ExitStatus>) {
+    // SAFETY: plain calls on stdin and the own process.
+    unsafe {
+        if libc::tcgetpgrp(libc::STDIN_FILENO) != pid as libc::pid_t {
+            return;
+        }
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        if status.and_then(|s| s.signal()) == Some(libc::SIGINT) {
+            libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+            libc::kill(libc::getpid(), libc::SIGINT);
+        }
+    }
+}
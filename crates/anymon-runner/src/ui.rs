@@ -0,0 +1,570 @@
+//! Full-screen dashboard for watch mode (`--ui`): one output pane per task,
+//! a status bar and a footer with recent file events.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::{cursor, execute, terminal};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{Frame, Terminal};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::control::{TaskAction, TaskCommand};
+use crate::events::FileEvent;
+use crate::output::{OutputLine, OutputTx, Stream};
+use crate::state::{Phase, SharedState};
+
+/// Lines kept per pane.
+const MAX_LINES: usize = 2000;
+/// Entries kept in the footer.
+const MAX_FOOTER: usize = 100;
+const FOOTER_HEIGHT: u16 = 7;
+const HELP: &str =
+    " q quit · r/R restart task/all · p pause · tab/1-9 select · ↑↓ PgUp PgDn End scroll · c clear ";
+
+/// The running dashboard. Dropping it without `stop` leaves the terminal to
+/// the dashboard thread until it notices the program ending.
+pub struct Ui {
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Ui {
+    /// Take over the terminal. Task output arrives on `output`; anything
+    /// anymon prints itself is captured as well and sent through `output_tx`.
+    /// `quit_tx` is signalled when the user quits.
+    pub fn start(
+        states: Vec<(String, SharedState)>,
+        output: mpsc::UnboundedReceiver<OutputLine>,
+        output_tx: OutputTx,
+        events: broadcast::Receiver<FileEvent>,
+        ctrl_tx: broadcast::Sender<TaskCommand>,
+        quit_tx: mpsc::Sender<()>,
+    ) -> io::Result<Ui> {
+        use std::io::IsTerminal;
+        if !io::stdout().is_terminal() {
+            return Err(io::Error::other("stdout is not a terminal"));
+        }
+        let screen = Screen::enter(output_tx)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let mut app = App::new(states, ctrl_tx);
+            std::thread::spawn(move || {
+                app.run(screen, output, events, &stop);
+                let _ = quit_tx.try_send(());
+            })
+        };
+        Ok(Ui {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Close the dashboard and give the terminal back.
+    pub fn stop(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The terminal while the dashboard owns it. Dropping it restores the
+/// terminal and anymon's own stdout and stderr.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<std::fs::File>>,
+    _redirect: Redirect,
+}
+
+impl Screen {
+    fn enter(output_tx: OutputTx) -> io::Result<Screen> {
+        let (redirect, mut tty) = Redirect::start(output_tx)?;
+        colored::control::set_override(false);
+        terminal::enable_raw_mode()?;
+        execute!(tty, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let terminal = Terminal::new(CrosstermBackend::new(tty))?;
+        Ok(Screen {
+            terminal,
+            _redirect: redirect,
+        })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(
+            self.terminal.backend_mut(),
+            terminal::LeaveAlternateScreen,
+            cursor::Show
+        );
+        colored::control::unset_override();
+    }
+}
+
+/// Points fds 1 and 2 at a pipe so that anymon's own log lines end up in
+/// the dashboard instead of on top of it.
+#[cfg(unix)]
+struct Redirect {
+    saved_out: libc::c_int,
+    saved_err: libc::c_int,
+}
+
+#[cfg(unix)]
+impl Redirect {
+    /// Returns the guard and a handle to the original stdout for drawing.
+    fn start(output_tx: OutputTx) -> io::Result<(Redirect, std::fs::File)> {
+        use std::io::{BufRead, Write};
+        use std::os::fd::FromRawFd;
+
+        let cloexec = |fd| {
+            // SAFETY: duplicates a descriptor we own; the copy is not
+            // inherited by task processes.
+            let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+            if dup < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(dup)
+            }
+        };
+        let _ = io::stdout().flush();
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for both ends of the pipe.
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_end, write_end] = fds;
+        // SAFETY: all descriptors are valid; the write end stays open as
+        // fds 1 and 2 until `drop` puts the originals back.
+        let (saved_out, saved_err, tty, reader) = unsafe {
+            libc::fcntl(read_end, libc::F_SETFD, libc::FD_CLOEXEC);
+            let saved_out = cloexec(libc::STDOUT_FILENO)?;
+            let saved_err = cloexec(libc::STDERR_FILENO)?;
+            let tty = std::fs::File::from_raw_fd(cloexec(saved_out)?);
+            libc::dup2(write_end, libc::STDOUT_FILENO);
+            libc::dup2(write_end, libc::STDERR_FILENO);
+            libc::close(write_end);
+            (
+                saved_out,
+                saved_err,
+                tty,
+                std::fs::File::from_raw_fd(read_end),
+            )
+        };
+
+        std::thread::spawn(move || {
+            let mut reader = io::BufReader::new(reader);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let text = String::from_utf8_lossy(&buf)
+                            .trim_end_matches(['\n', '\r'])
+                            .to_string();
+                        let line = OutputLine {
+                            task: None,
+                            stream: Stream::Anymon,
+                            text,
+                        };
+                        if output_tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok((
+            Redirect {
+                saved_out,
+                saved_err,
+            },
+            tty,
+        ))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Redirect {
+    fn drop(&mut self) {
+        use std::io::Write;
+        let _ = io::stdout().flush();
+        // SAFETY: restores the descriptors saved in `start`.
+        unsafe {
+            libc::dup2(self.saved_out, libc::STDOUT_FILENO);
+            libc::dup2(self.saved_err, libc::STDERR_FILENO);
+            libc::close(self.saved_out);
+            libc::close(self.saved_err);
+        }
+    }
+}
+
+/// Redirecting anymon's own output is only implemented on unix.
+#[cfg(not(unix))]
+struct Redirect;
+
+#[cfg(not(unix))]
+impl Redirect {
+    fn start(_output_tx: OutputTx) -> io::Result<(Redirect, std::fs::File)> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--ui is only supported on unix",
+        ))
+    }
+}
+
+/// Scrollback of one task.
+#[derive(Default)]
+struct Pane {
+    lines: VecDeque<(Stream, String)>,
+    /// Lines scrolled up from the bottom; 0 follows new output.
+    scroll: usize,
+}
+
+impl Pane {
+    fn push(&mut self, stream: Stream, text: String) {
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((stream, text));
+        // Keep the view still while the user is reading back.
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.lines.len());
+        }
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(delta)
+            .min(self.lines.len());
+    }
+
+    /// The lines that fit in `height` rows.
+    fn visible(&self, height: usize) -> impl Iterator<Item = &(Stream, String)> {
+        let end = self.lines.len() - self.scroll.min(self.lines.len());
+        let start = end.saturating_sub(height);
+        self.lines.range(start..end)
+    }
+}
+
+enum Action {
+    Quit,
+    Send(TaskCommand),
+}
+
+struct App {
+    states: Vec<(String, SharedState)>,
+    panes: Vec<Pane>,
+    selected: usize,
+    footer: VecDeque<String>,
+    ctrl_tx: broadcast::Sender<TaskCommand>,
+    cwd: Option<std::path::PathBuf>,
+}
+
+impl App {
+    fn new(states: Vec<(String, SharedState)>, ctrl_tx: broadcast::Sender<TaskCommand>) -> App {
+        App {
+            panes: states.iter().map(|_| Pane::default()).collect(),
+            states,
+            selected: 0,
+            footer: VecDeque::new(),
+            ctrl_tx,
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+
+    fn run(
+        &mut self,
+        mut screen: Screen,
+        mut output: mpsc::UnboundedReceiver<OutputLine>,
+        mut events: broadcast::Receiver<FileEvent>,
+        stop: &AtomicBool,
+    ) {
+        while !stop.load(Ordering::Relaxed) {
+            while let Ok(line) = output.try_recv() {
+                self.add_line(line);
+            }
+            loop {
+                match events.try_recv() {
+                    Ok(event) => {
+                        let path = self
+                            .cwd
+                            .as_deref()
+                            .and_then(|cwd| event.path.strip_prefix(cwd).ok())
+                            .unwrap_or(&event.path);
+                        self.note(format!("{} {}", event.kind.as_str(), path.display()));
+                    }
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+            if screen.terminal.draw(|f| self.draw(f)).is_err() {
+                return;
+            }
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.key(key) {
+                Some(Action::Quit) => return,
+                Some(Action::Send(cmd)) => {
+                    let _ = self.ctrl_tx.send(cmd);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn add_line(&mut self, line: OutputLine) {
+        let (task, text) = match line.stream {
+            Stream::Anymon => {
+                let (task, text) = route(&line.text);
+                (task.map(str::to_string), text.to_string())
+            }
+            _ => (line.task, line.text),
+        };
+        let pane = task.and_then(|t| self.states.iter().position(|(name, _)| *name == t));
+        match pane {
+            Some(i) => self.panes[i].push(line.stream, strip_ansi(&text)),
+            None => self.note(strip_ansi(&text)),
+        }
+    }
+
+    fn note(&mut self, text: String) {
+        if self.footer.len() == MAX_FOOTER {
+            self.footer.pop_front();
+        }
+        self.footer.push_back(text);
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Action> {
+        let n = self.panes.len();
+        let task = |action, target: Option<&String>| {
+            Some(Action::Send(TaskCommand {
+                action,
+                target: target.cloned(),
+            }))
+        };
+        let selected = self.states.get(self.selected).map(|(name, _)| name);
+        let pane = &mut self.panes[self.selected];
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Action::Quit)
+            }
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Char('r') => return task(TaskAction::Restart, selected),
+            KeyCode::Char('R') => return task(TaskAction::Restart, None),
+            KeyCode::Char('p') => {
+                let paused = self.states[self.selected]
+                    .1
+                    .lock()
+                    .is_ok_and(|state| state.paused);
+                let action = if paused {
+                    TaskAction::Resume
+                } else {
+                    TaskAction::Pause
+                };
+                return task(action, selected);
+            }
+            KeyCode::Char('c') => *pane = Pane::default(),
+            KeyCode::Tab | KeyCode::Right => self.selected = (self.selected + 1) % n,
+            KeyCode::BackTab | KeyCode::Left => self.selected = (self.selected + n - 1) % n,
+            KeyCode::Char(c @ '1'..='9') => {
+                let i = c as usize - '1' as usize;
+                if i < n {
+                    self.selected = i;
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => pane.scroll_by(1),
+            KeyCode::Down | KeyCode::Char('j') => pane.scroll_by(-1),
+            KeyCode::PageUp => pane.scroll_by(10),
+            KeyCode::PageDown => pane.scroll_by(-10),
+            KeyCode::Home | KeyCode::Char('g') => pane.scroll = pane.lines.len(),
+            KeyCode::End | KeyCode::Char('G') => pane.scroll = 0,
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&self, f: &mut Frame) {
+        let [status, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(FOOTER_HEIGHT),
+        ])
+        .areas(f.area());
+
+        f.render_widget(Paragraph::new(self.status_line()), status);
+
+        let n = self.panes.len();
+        let cols = n.clamp(1, 3);
+        let rows = n.div_ceil(cols);
+        let row_areas = Layout::vertical(vec![Constraint::Fill(1); rows]).split(body);
+        for (r, row) in row_areas.iter().enumerate() {
+            let in_row = cols.min(n - r * cols);
+            let cells = Layout::horizontal(vec![Constraint::Fill(1); in_row]).split(*row);
+            for (c, cell) in cells.iter().enumerate() {
+                self.draw_pane(f, r * cols + c, *cell);
+            }
+        }
+
+        let height = FOOTER_HEIGHT.saturating_sub(2) as usize;
+        let skip = self.footer.len().saturating_sub(height);
+        let lines: Vec<Line> = self
+            .footer
+            .iter()
+            .skip(skip)
+            .map(|s| Line::raw(s.as_str()))
+            .collect();
+        let block = Block::bordered()
+            .title(" events ")
+            .title_bottom(Line::raw(HELP).right_aligned());
+        f.render_widget(Paragraph::new(lines).block(block), footer);
+    }
+
+    fn draw_pane(&self, f: &mut Frame, i: usize, area: Rect) {
+        let pane = &self.panes[i];
+        let mut title = format!(" {} {} ", i + 1, self.states[i].0);
+        if pane.scroll > 0 {
+            title.push_str(&format!("↑{} ", pane.scroll));
+        }
+        let border = if i == self.selected {
+            Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+        } else {
+            Style::new().fg(Color::DarkGray)
+        };
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = pane
+            .visible(height)
+            .map(|(stream, text)| {
+                let style = match stream {
+                    Stream::Stdout => Style::new(),
+                    Stream::Stderr => Style::new().fg(Color::Red),
+                    Stream::Anymon => Style::new().fg(Color::Cyan),
+                };
+                Line::styled(text.as_str(), style)
+            })
+            .collect();
+        let block = Block::bordered().title(title).border_style(border);
+        f.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    /// `1 web running · 2 api exited (exit 1)`, coloured by phase.
+    fn status_line(&self) -> Line<'_> {
+        let mut spans = Vec::new();
+        for (i, (name, state)) in self.states.iter().enumerate() {
+            let Ok(state) = state.lock() else {
+                continue;
+            };
+            if i > 0 {
+                spans.push(Span::raw(" · "));
+            }
+            let (phase, color) = match &state.phase {
+                Phase::Idle => ("stopped", Color::DarkGray),
+                Phase::Waiting(_) => ("waiting", Color::Yellow),
+                Phase::Running { .. } => ("running", Color::Green),
+                Phase::Exited { status, .. } if status.success() => ("exited", Color::Blue),
+                Phase::Exited { .. } => ("exited", Color::Red),
+                Phase::CrashLooping { .. } => ("crash-looping", Color::Red),
+            };
+            let mut name_style = Style::new().add_modifier(Modifier::BOLD);
+            if i == self.selected {
+                name_style = name_style.add_modifier(Modifier::REVERSED);
+            }
+            spans.push(Span::styled(format!("{} {}", i + 1, name), name_style));
+            spans.push(Span::styled(format!(" {}", phase), Style::new().fg(color)));
+            if state.paused {
+                spans.push(Span::styled(" paused", Style::new().fg(Color::Yellow)));
+            }
+            if let Some(status) = state.last_exit {
+                spans.push(Span::raw(format!(" (exit {})", crate::exit_code(status))));
+            }
+        }
+        Line::from(spans)
+    }
+}
+
+/// Split a line anymon printed into the task it is about (`[anymon] [web]:
+/// ...`) and the message.
+fn route(text: &str) -> (Option<&str>, &str) {
+    let rest = text.strip_prefix("[anymon] ").unwrap_or(text);
+    if let Some((task, msg)) = rest
+        .strip_prefix('[')
+        .and_then(|tagged| tagged.split_once("]: "))
+    {
+        return (Some(task), msg);
+    }
+    (None, rest)
+}
+
+/// Drop escape sequences and other control characters that would garble
+/// the panes; tabs become spaces.
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // CSI sequences end with a byte in @..~; others are two chars.
+                if chars.next_if_eq(&'[').is_some() {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                } else {
+                    chars.next();
+                }
+            }
+            '\t' => out.push_str("    "),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_and_cleans_lines() {
+        assert_eq!(
+            route("[anymon] [web]: restarting"),
+            (Some("web"), "restarting")
+        );
+        assert_eq!(route("[anymon] watching: /p"), (None, "watching: /p"));
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m:\tx"), "error:    x");
+
+        let mut pane = Pane::default();
+        for i in 0..5 {
+            pane.push(Stream::Stdout, i.to_string());
+        }
+        pane.scroll_by(2);
+        pane.push(Stream::Stdout, "5".into());
+        let shown: Vec<&str> = pane.visible(2).map(|(_, t)| t.as_str()).collect();
+        assert_eq!(shown, ["1", "2"]);
+        pane.scroll_by(-10);
+        let shown: Vec<&str> = pane.visible(2).map(|(_, t)| t.as_str()).collect();
+        assert_eq!(shown, ["4", "5"]);
+    }
+}
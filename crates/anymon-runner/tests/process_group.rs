@@ -0,0 +1,47 @@
+#![cfg(unix)]
+
+use anymon_runner::output::OutputSink;
+use anymon_runner::{stop_child, try_spawn, Command, SpawnOptions, StopSignal};
+use std::time::Duration;
+
+fn group_alive(pgid: u32) -> bool {
+    unsafe { libc::kill(-(pgid as libc::pid_t), 0) == 0 }
+}
+
+/// Options for a task whose output is captured, like the default
+/// `output = "prefix"`.
+fn captured() -> SpawnOptions {
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    SpawnOptions {
+        output: Some(OutputSink {
+            task: "test".to_string(),
+            tx,
+            log: None,
+        }),
+        ..SpawnOptions::default()
+    }
+}
+
+async fn assert_stop_kills_grandchildren(opts: SpawnOptions) {
+    // The shell is the direct child and `sleep` is the grandchild that used
+    // to survive restarts.
+    let cmd = Command::Line("sh -c 'sleep 100 & wait'".to_string());
+    let mut child = try_spawn(&cmd, &opts).expect("spawn");
+    let pgid = child.id().expect("pid");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(group_alive(pgid));
+
+    let status = stop_child("test", &mut child, StopSignal::Term, 2000).await;
+    assert!(status.is_some());
+    assert!(!group_alive(pgid), "processes survived in group {pgid}");
+}
+
+#[tokio::test]
+async fn stop_kills_grandchildren() {
+    assert_stop_kills_grandchildren(captured()).await;
+}
+
+#[tokio::test]
+async fn stop_kills_grandchildren_with_inherited_output() {
+    assert_stop_kills_grandchildren(SpawnOptions::default()).await;
+}
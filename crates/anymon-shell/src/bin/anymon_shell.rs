@@ -15,7 +15,15 @@ fn main() {
     let cmd = args.remove(1);
     let cmd_args: Vec<String> = args.into_iter().skip(1).collect();
 
-    match anymon_shell::run_command(cmd.clone(), &cmd_args) {
+    // A single argument is treated as a whole command line, e.g.
+    // `anymon-shell "echo 'Hello world'"`.
+    let result = if cmd_args.is_empty() {
+        anymon_shell::run_command_line(&cmd)
+    } else {
+        anymon_shell::run_command(cmd.clone(), &cmd_args)
+    };
+
+    match result {
         Ok(out) => {
             if !out.stdout.is_empty() {
                 print!("{}", out.stdout);
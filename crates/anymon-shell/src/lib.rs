@@ -3,8 +3,16 @@
 //! Small crate exposing utilities to run system commands directly
 //! without going through an external interactive shell.
 
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+mod words;
+
+pub use words::{
+    parse_command_line, parse_command_line_with, quote, quote_powershell, CommandLine, ParseError,
+    ParseErrorKind,
+};
+
 /// Result of running a command.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CommandOutput {
@@ -38,6 +46,78 @@ pub fn run_command<S: AsRef<str>>(cmd: S, args: &[S]) -> Result<CommandOutput, s
     })
 }
 
+/// Run a command line such as `echo 'Hello world'`, capturing its output.
+///
+/// The line is split with [`parse_command_line`] and spawned directly. Only
+/// lines that use shell syntax (pipes, redirects, ...) are handed to the
+/// platform shell (`sh -c` or `powershell -NoProfile -Command`).
+pub fn run_command_line(line: &str) -> Result<CommandOutput, std::io::Error> {
+    match parse_command_line(line)? {
+        CommandLine::Argv(words) => match words.split_first() {
+            Some((prog, args)) => run_command(
+                prog.as_str(),
+                &args.iter().map(String::as_str).collect::<Vec<_>>(),
+            ),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "empty command",
+            )),
+        },
+        CommandLine::Shell => {
+            if cfg!(windows) {
+                run_command("powershell", &["-NoProfile", "-Command", line])
+            } else {
+                run_command("sh", &["-c", line])
+            }
+        }
+    }
+}
+
+/// Resolve `name` the way spawning it would: a name containing a path
+/// separator is taken as a path (relative to `cwd` if given), anything else
+/// is searched for in `PATH` (trying the `PATHEXT` extensions on Windows).
+pub fn find_executable(name: &str, cwd: Option<&Path>) -> Option<PathBuf> {
+    let p = Path::new(name);
+    if p.components().count() > 1 || name.contains(std::path::MAIN_SEPARATOR) {
+        let p = match cwd {
+            Some(dir) if p.is_relative() => dir.join(p),
+            _ => p.to_path_buf(),
+        };
+        return p.exists().then_some(p);
+    }
+
+    let paths = std::env::var_os("PATH")?;
+
+    #[cfg(windows)]
+    let pathext = std::env::var_os("PATHEXT").unwrap_or_else(|| ".EXE;.CMD;.BAT;.COM".into());
+    #[cfg(windows)]
+    let exts: Vec<String> = pathext
+        .to_string_lossy()
+        .split(';')
+        .map(|s| s.to_string())
+        .collect();
+
+    for dir in std::env::split_paths(&paths) {
+        #[cfg(windows)]
+        {
+            for ext in &exts {
+                let candidate = dir.join(format!("{}{}", name, ext));
+                if candidate.exists() {
+                    return Some(candidate);
+                }
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            let candidate = dir.join(name);
+            if candidate.exists() {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,4 +138,13 @@ mod tests {
             assert!(res.stdout.contains("hello"));
         }
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_line_keeps_quoted_words() {
+        let res = run_command_line("printf '%s|' 'Hello world' x").expect("run printf");
+        assert_eq!(res.stdout, "Hello world|x|");
+        let res = run_command_line("echo piped | tr a-z A-Z").expect("run pipe");
+        assert_eq!(res.stdout.trim(), "PIPED");
+    }
 }
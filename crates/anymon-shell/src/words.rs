@@ -0,0 +1,389 @@
+//! POSIX-style splitting of command lines into argv words.
+//!
+//! Handles single and double quotes, backslash escapes and `$VAR` /
+//! `${VAR}` expansion inside double quotes. Anything that needs a real shell
+//! to run (pipes, redirects, command lists, subshells, command substitution,
+//! globs, `~`, unquoted expansions that would be field-split and leading
+//! `NAME=value` assignments) is detected and reported as
+//! [`CommandLine::Shell`] instead of being guessed at.
+
+use std::fmt;
+
+/// How a parsed command line should be executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandLine {
+    /// Plain words that can be spawned directly, program first.
+    Argv(Vec<String>),
+    /// The line uses shell syntax and has to be handed to a shell as-is.
+    Shell,
+}
+
+/// Why a command line could not be split.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset in the input where the problem starts.
+    pub pos: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnterminatedSingleQuote,
+    UnterminatedDoubleQuote,
+    UnterminatedBrace,
+    DanglingEscape,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            ParseErrorKind::UnterminatedSingleQuote => "unterminated single quote",
+            ParseErrorKind::UnterminatedDoubleQuote => "unterminated double quote",
+            ParseErrorKind::UnterminatedBrace => "unterminated ${...}",
+            ParseErrorKind::DanglingEscape => "trailing backslash",
+        };
+        write!(f, "{} at position {}", what, self.pos)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(e: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Parse `line`, expanding variables from the process environment.
+pub fn parse_command_line(line: &str) -> Result<CommandLine, ParseError> {
+    parse_command_line_with(line, |name| std::env::var(name).ok())
+}
+
+/// Parse `line`, expanding variables with `lookup`. Unset variables expand
+/// to nothing, as in a shell.
+pub fn parse_command_line_with<F>(line: &str, lookup: F) -> Result<CommandLine, ParseError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // A word exists once anything (even an empty quote pair) was seen.
+    let mut in_word = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                i += 1;
+            }
+            '#' if !in_word => break,
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' | '`' => return Ok(CommandLine::Shell),
+            // Unquoted globs and a leading `~` are expanded by the shell.
+            '*' | '?' | '[' => return Ok(CommandLine::Shell),
+            '~' if !in_word => return Ok(CommandLine::Shell),
+            // `FOO=1 cmd` sets a variable for `cmd` rather than running `FOO=1`.
+            '=' if words.is_empty() && is_name(&raw_word(&chars, i)) => {
+                return Ok(CommandLine::Shell)
+            }
+            '\'' => {
+                in_word = true;
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some((_, '\'')) => break,
+                        Some((_, ch)) => word.push(*ch),
+                        None => {
+                            return Err(ParseError {
+                                pos,
+                                kind: ParseErrorKind::UnterminatedSingleQuote,
+                            })
+                        }
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '"' => {
+                in_word = true;
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.get(i + 1) {
+                            Some((_, next @ ('$' | '`' | '"' | '\\'))) => {
+                                word.push(*next);
+                                i += 2;
+                            }
+                            Some((_, '\n')) => i += 2,
+                            _ => {
+                                word.push('\\');
+                                i += 1;
+                            }
+                        },
+                        Some((_, '`')) => return Ok(CommandLine::Shell),
+                        Some((_, '$')) => match expand_at(&chars, i, &lookup)? {
+                            Expansion::Value(value, next) => {
+                                word.push_str(&value);
+                                i = next;
+                            }
+                            Expansion::NeedsShell => return Ok(CommandLine::Shell),
+                        },
+                        Some((_, ch)) => {
+                            word.push(*ch);
+                            i += 1;
+                        }
+                        None => {
+                            return Err(ParseError {
+                                pos,
+                                kind: ParseErrorKind::UnterminatedDoubleQuote,
+                            })
+                        }
+                    }
+                }
+                i += 1;
+            }
+            '\\' => match chars.get(i + 1) {
+                // Line continuation
+                Some((_, '\n')) => i += 2,
+                Some((_, next)) => {
+                    word.push(*next);
+                    in_word = true;
+                    i += 2;
+                }
+                None => {
+                    return Err(ParseError {
+                        pos,
+                        kind: ParseErrorKind::DanglingEscape,
+                    })
+                }
+            },
+            '$' => match expand_at(&chars, i, &lookup)? {
+                // A lone `$` is literal.
+                Expansion::Value(value, next) if next == i + 1 => {
+                    in_word = true;
+                    word.push_str(&value);
+                    i = next;
+                }
+                // Unquoted expansions are split into words (and globbed) by a
+                // shell.
+                _ => return Ok(CommandLine::Shell),
+            },
+            _ => {
+                word.push(c);
+                in_word = true;
+                i += 1;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(CommandLine::Argv(words))
+}
+
+enum Expansion {
+    /// Expanded text and the index of the first char after the reference.
+    Value(String, usize),
+    NeedsShell,
+}
+
+/// Expand the variable reference starting at the `$` in `chars[i]`.
+fn expand_at<F>(chars: &[(usize, char)], i: usize, lookup: &F) -> Result<Expansion, ParseError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match chars.get(i + 1) {
+        Some((_, '(')) => Ok(Expansion::NeedsShell),
+        Some((_, '{')) => {
+            let start = i + 2;
+            let end = chars[start..]
+                .iter()
+                .position(|(_, c)| *c == '}')
+                .map(|n| start + n)
+                .ok_or(ParseError {
+                    pos: chars[i].0,
+                    kind: ParseErrorKind::UnterminatedBrace,
+                })?;
+            let name: String = chars[start..end].iter().map(|(_, c)| *c).collect();
+            if !is_name(&name) {
+                // ${VAR:-default} and friends are left to a real shell.
+                return Ok(Expansion::NeedsShell);
+            }
+            Ok(Expansion::Value(lookup(&name).unwrap_or_default(), end + 1))
+        }
+        Some((_, c)) if c.is_ascii_alphabetic() || *c == '_' => {
+            let start = i + 1;
+            let end = chars[start..]
+                .iter()
+                .position(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+                .map(|n| start + n)
+                .unwrap_or(chars.len());
+            let name: String = chars[start..end].iter().map(|(_, c)| *c).collect();
+            Ok(Expansion::Value(lookup(&name).unwrap_or_default(), end))
+        }
+        // Special parameters ($?, $1, $$, ...) only make sense in a shell.
+        Some((_, c)) if c.is_ascii_digit() || "?!$#*@-".contains(*c) => Ok(Expansion::NeedsShell),
+        // A lone `$` is literal.
+        _ => Ok(Expansion::Value("$".to_string(), i + 1)),
+    }
+}
+
+/// Quote `word` so that [`parse_command_line`] reads it back as a single,
+/// unexpanded word. Words without special characters are returned as-is.
+pub fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote `word` as a single literal argument for PowerShell. Words without
+/// special characters are returned as-is.
+pub fn quote_powershell(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:\\".contains(c));
+    if plain {
+        return word.to_string();
+    }
+    // Inside single quotes only a quote (PowerShell also takes the curly
+    // ones) needs escaping, by doubling it.
+    let mut out = String::from("'");
+    for c in word.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            out.push(c);
+        }
+        out.push(c);
+    }
+    out.push('\'');
+    out
+}
+
+/// The unparsed text of the word that ends just before `chars[i]`.
+fn raw_word(chars: &[(usize, char)], i: usize) -> String {
+    let start = chars[..i]
+        .iter()
+        .rposition(|(_, c)| c.is_whitespace())
+        .map_or(0, |n| n + 1);
+    chars[start..i].iter().map(|(_, c)| *c).collect()
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(line: &str) -> Vec<String> {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        match parse_command_line_with(line, lookup).unwrap() {
+            CommandLine::Argv(words) => words,
+            CommandLine::Shell => panic!("expected argv for {line:?}"),
+        }
+    }
+
+    #[test]
+    fn splits_quotes_and_escapes() {
+        assert_eq!(argv("echo 'Hello world'"), ["echo", "Hello world"]);
+        assert_eq!(
+            argv(r#"echo "a \"b\" c" d\ e"#),
+            ["echo", r#"a "b" c"#, "d e"]
+        );
+        assert_eq!(argv("printf '' x"), ["printf", "", "x"]);
+        assert_eq!(argv("a'b'\"c\"d # comment"), ["abcd"]);
+    }
+
+    #[test]
+    fn expands_variables() {
+        assert_eq!(argv("ls \"$HOME/src\""), ["ls", "/home/me/src"]);
+        assert_eq!(
+            argv("ls \"${HOME}\"x '$HOME'"),
+            ["ls", "/home/mex", "$HOME"]
+        );
+        assert_eq!(
+            argv("echo \"$UNSET\" \"$EMPTY\" 5$"),
+            ["echo", "", "", "5$"]
+        );
+    }
+
+    #[test]
+    fn detects_shell_syntax() {
+        for line in [
+            "cargo build | tee log",
+            "echo hi > out.txt",
+            "make && make test",
+            "echo $(date)",
+            "echo `date`",
+            "exit $?",
+            "ls *.txt",
+            "rm -f out/[ab].o",
+            "ls ~",
+            "cat ~/notes",
+            "printf '[%s]' $FLAGS",
+            "ls ${HOME}/src",
+            "FOO=1 env",
+            "A_1= make",
+        ] {
+            assert_eq!(parse_command_line(line).unwrap(), CommandLine::Shell);
+        }
+        for line in [
+            "echo '|'",
+            "ls '*.txt' \\? \"~\"",
+            "git log HEAD~1",
+            "make CC=clang",
+            "'FOO'=1 env",
+            "./FOO=1",
+        ] {
+            assert!(matches!(
+                parse_command_line(line).unwrap(),
+                CommandLine::Argv(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        for word in ["plain", "", "two words", "it's", "$HOME", "a|b"] {
+            assert_eq!(argv(&format!("x {}", quote(word))), ["x", word]);
+        }
+    }
+
+    #[test]
+    fn quotes_for_powershell() {
+        assert_eq!(quote_powershell(r"C:\src\a.rs"), r"C:\src\a.rs");
+        assert_eq!(quote_powershell("a b"), "'a b'");
+        assert_eq!(quote_powershell("it's $x"), "'it''s $x'");
+        assert_eq!(quote_powershell(""), "''");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let err = parse_command_line("echo 'oops").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedSingleQuote);
+        assert_eq!(err.pos, 5);
+        let err = parse_command_line("echo \"${HOME\"").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedBrace);
+        let err = parse_command_line("echo \"oops").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedDoubleQuote);
+    }
+}